@@ -11,15 +11,18 @@ use gfx_hal::{
 };
 use std::{
 	any::TypeId,
+	iter::once,
 	marker::PhantomData,
 	mem::{
 		size_of,
 		MaybeUninit,
 	},
 	ops::Range,
+	slice,
 	sync::Arc,
 };
 
+use bytemuck::Pod;
 use gfx_memory::{
 	Block,
 	MemoryAllocator,
@@ -29,6 +32,7 @@ use gfx_memory::{
 
 use crate::{
 	gfx_back::Backend,
+	shader::IndexType,
 	util::TakeExt,
 	CommandPool,
 	Fence,
@@ -37,6 +41,8 @@ use crate::{
 
 use self::inner::InnerBuffer;
 
+pub const DEFAULT_STAGING_SIZE: buffer::Offset = 64 * 1024 * 1024;
+
 pub(crate) mod inner {
 	use crate::{
 		gfx_back::Backend,
@@ -55,6 +61,18 @@ pub(crate) mod inner {
 
 pub trait Buffer<'a>: Sized + InnerBuffer {
 	fn create<'b>(data: &'a HALData, descs: &'b [BufferViewDesc]) -> Vec<BufferView<'a, Self>>;
+
+	/// Total alignment padding a buffer created from `descs` would contain.
+	fn total_wasted_bytes(data: &HALData, descs: &[BufferViewDesc]) -> u64 {
+		let usage = descs
+			.iter()
+			.fold(Usage::empty(), |usage, desc| usage | desc.usage);
+		BaseBuffer::aligned_sizes(data, descs, usage)
+			.iter()
+			.zip(descs.iter())
+			.map(|(size, desc)| size - desc.len * desc.type_size)
+			.sum()
+	}
 }
 
 pub(crate) struct BaseBuffer<'a> {
@@ -62,6 +80,7 @@ pub(crate) struct BaseBuffer<'a> {
 	block: MaybeUninit<<SmartAllocator<Backend> as MemoryAllocator<Backend>>::Block>,
 	buffer: MaybeUninit<<Backend as gfx_hal::Backend>::Buffer>,
 	size_in_bytes: buffer::Offset,
+	properties: Properties,
 }
 
 impl InnerBuffer for BaseBuffer<'_> {
@@ -82,34 +101,48 @@ impl<'a> BaseBuffer<'a> {
 		descs: &'b [BufferViewDesc],
 		extra_usage: Usage,
 		props: Properties,
+		preferred: Properties,
 	) -> (Vec<buffer::Offset>, Self) {
 		let usage = extra_usage |
 			descs
 				.iter()
 				.fold(Usage::empty(), |usage, desc| usage | desc.usage);
+		let sizes = Self::aligned_sizes(data, descs, usage);
+
+		let size_in_bytes = sizes.iter().fold(0, |len, add_len| len + add_len);
+		(
+			sizes,
+			Self::create(data, usage, props, preferred, size_in_bytes),
+		)
+	}
+
+	fn aligned_sizes(
+		data: &HALData,
+		descs: &[BufferViewDesc],
+		usage: Usage,
+	) -> Vec<buffer::Offset> {
 		let align = if usage.contains(Usage::UNIFORM) {
-			let phys_device = &data.adapter().physical_device;
-			phys_device.limits().min_uniform_buffer_offset_alignment
+			data.limits().min_uniform_buffer_offset_alignment
 		} else {
 			1
 		};
-		let sizes = descs
+		descs
 			.iter()
 			.map(|desc| {
 				let size = desc.len * desc.type_size;
 				let padding = (align - (size % align)) % align;
 				size + padding
 			})
-			.collect::<Vec<_>>();
-
-		let size_in_bytes = sizes.iter().fold(0, |len, add_len| len + add_len);
-		(sizes, Self::create(data, usage, props, size_in_bytes))
+			.collect()
 	}
 
-	fn create(
+	/// Allocates memory with all of `props`, and with `preferred` as well if
+	/// the device has such a memory type.
+	pub(crate) fn create(
 		data: &'a HALData,
 		usage: Usage,
 		props: Properties,
+		preferred: Properties,
 		size_in_bytes: buffer::Offset,
 	) -> Self {
 		println!("Creating Buffer");
@@ -119,8 +152,9 @@ impl<'a> BaseBuffer<'a> {
 
 			let mut buffer = device.create_buffer(size_in_bytes, usage).unwrap();
 			let reqs = device.get_buffer_requirements(&buffer);
+			let properties = Self::memory_type_properties(data, reqs.type_mask, props, preferred);
 			let block = allocator
-				.alloc(device, (Type::General, props), reqs)
+				.alloc(device, (Type::General, properties), reqs)
 				.unwrap();
 			device
 				.bind_buffer_memory(block.memory(), block.range().start, &mut buffer)
@@ -130,9 +164,94 @@ impl<'a> BaseBuffer<'a> {
 				block: MaybeUninit::new(block),
 				buffer: MaybeUninit::new(buffer),
 				size_in_bytes,
+				properties,
 			}
 		}
 	}
+
+	/// The properties of the first memory type in `type_mask` with `props`
+	/// and `preferred`, falling back to the first with `props` alone. The
+	/// allocator is asked for all of them, so the memory it hands out has at
+	/// least these properties.
+	fn memory_type_properties(
+		data: &HALData,
+		type_mask: u64,
+		props: Properties,
+		preferred: Properties,
+	) -> Properties {
+		let memory_types = data
+			.adapter()
+			.physical_device
+			.memory_properties()
+			.memory_types;
+		let find = |wanted: Properties| {
+			memory_types
+				.iter()
+				.enumerate()
+				.filter(|&(id, _)| type_mask & (1 << id) != 0)
+				.map(|(_, ty)| ty.properties)
+				.find(|properties| properties.contains(wanted))
+		};
+		find(props | preferred)
+			.or_else(|| find(props))
+			.unwrap_or_else(|| panic!("The device has no memory type with {:?}", props))
+	}
+
+	/// Whether CPU writes are visible to the device without flushing, going
+	/// by the memory type the buffer was allocated from.
+	pub(crate) fn is_coherent(&self) -> bool { self.properties.contains(Properties::COHERENT) }
+
+	/// Maps the atom aligned range around `range`, returning a pointer to the
+	/// start of `range` and the range that was actually mapped. Only the
+	/// mapped range may be flushed or invalidated.
+	pub(crate) unsafe fn map_range(
+		&self,
+		range: Range<buffer::Offset>,
+	) -> (*mut u8, Range<buffer::Offset>) {
+		let mapped = self.atom_range(range.clone());
+		let map = self
+			.data
+			.device()
+			.map_memory(self.block().memory(), mapped.clone())
+			.unwrap();
+		(map.add((range.start - mapped.start) as usize), mapped)
+	}
+
+	/// Makes CPU writes to the `mapped` range visible to the device.
+	fn flush(&self, mapped: Range<buffer::Offset>) {
+		if !self.is_coherent() {
+			let memory = self.block().memory();
+			unsafe {
+				self.data
+					.device()
+					.flush_mapped_memory_ranges(once((memory, mapped)))
+					.unwrap()
+			}
+		}
+	}
+
+	/// Makes device writes to the `mapped` range visible to the CPU.
+	pub(crate) fn invalidate(&self, mapped: Range<buffer::Offset>) {
+		if !self.is_coherent() {
+			let memory = self.block().memory();
+			unsafe {
+				self.data
+					.device()
+					.invalidate_mapped_memory_ranges(once((memory, mapped)))
+					.unwrap()
+			}
+		}
+	}
+
+	// Mapped ranges must be aligned to the non coherent atom size to be
+	// flushed.
+	fn atom_range(&self, range: Range<buffer::Offset>) -> Range<buffer::Offset> {
+		let atom = self.data.limits().non_coherent_atom_size as buffer::Offset;
+		let block = self.block().range();
+		let start = (range.start / atom * atom).max(block.start);
+		let end = ((range.end + atom - 1) / atom * atom).min(block.end);
+		start..end
+	}
 }
 
 impl Drop for BaseBuffer<'_> {
@@ -156,9 +275,23 @@ pub struct GPUBuffer<'a>(BaseBuffer<'a>);
 pub struct StagingBuffer<'a> {
 	base: BaseBuffer<'a>,
 	pub(crate) command_pool: &'a CommandPool<'a>,
+	/// Signaled while no submission reads from or writes to the buffer, so
+	/// it can be waited on before every use.
 	pub(crate) fence: Fence<'a>,
 }
 
+pub struct BufferPool<'a> {
+	data: &'a HALData,
+	command_pool: &'a CommandPool<'a>,
+	staging: StagingBuffer<'a>,
+}
+
+/// Device local index data with its element count.
+pub struct IndexBuffer<'a, I: IndexType + 'static> {
+	view: BufferView<'a, GPUBuffer<'a>>,
+	phantom: PhantomData<I>,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct BufferViewDesc {
 	type_id: TypeId,
@@ -166,6 +299,7 @@ pub struct BufferViewDesc {
 	usage: Usage,
 	len: buffer::Offset,
 	offset: buffer::Offset,
+	padding: buffer::Offset,
 }
 
 impl BufferViewDesc {
@@ -176,6 +310,7 @@ impl BufferViewDesc {
 			usage,
 			len,
 			offset: 0,
+			padding: 0,
 		}
 	}
 
@@ -186,6 +321,24 @@ impl BufferViewDesc {
 	pub(crate) fn len(&self) -> buffer::Offset { self.len }
 
 	pub(crate) fn offset(&self) -> buffer::Offset { self.offset }
+
+	/// Absolute byte range in the buffer of the elements in `range`.
+	pub(crate) fn byte_range(&self, range: Range<usize>) -> Range<buffer::Offset> {
+		assert!(
+			range.start <= range.end,
+			"Descriptor range starts after it ends: {:?}",
+			range
+		);
+		assert!(
+			range.end as buffer::Offset <= self.len,
+			"Descriptor range {:?} is out of bounds for a view of {} elements",
+			range,
+			self.len
+		);
+		let abs_beg = self.offset + (range.start as buffer::Offset * self.type_size);
+		let abs_end = self.offset + (range.end as buffer::Offset * self.type_size);
+		abs_beg..abs_end
+	}
 }
 
 pub struct BufferView<'a, T: Buffer<'a>> {
@@ -208,6 +361,7 @@ impl<'a, T: Buffer<'a>> BufferView<'a, T> {
 				buffer: buffer.clone(),
 				desc: {
 					let mut desc = *desc;
+					desc.padding = size - desc.len * desc.type_size;
 					desc.offset = {
 						let tmp = base_offset;
 						base_offset += size;
@@ -223,18 +377,65 @@ impl<'a, T: Buffer<'a>> BufferView<'a, T> {
 	pub fn descriptor(&self) -> Descriptor<Backend> { self.descriptor_to_end(0) }
 
 	pub fn descriptor_to_end(&self, start: usize) -> Descriptor<Backend> {
-		self.descriptor_range(0..self.desc.len as usize)
+		self.descriptor_range(start..self.element_count())
 	}
 
 	pub fn descriptor_range(&self, range: Range<usize>) -> Descriptor<Backend> {
-		let range: Range<buffer::Offset> = range.start as _..range.end as _;
-		assert!(range.start <= range.end);
-		assert!(range.end <= self.desc().len);
-		let abs_beg = self.offset() + (range.start * self.desc().type_size);
-		let abs_end = self.offset() + (range.end * self.desc().type_size);
-		Descriptor::Buffer(self.hal_buffer(), Some(abs_beg)..Some(abs_end))
+		let range = self.desc.byte_range(range);
+		Descriptor::Buffer(self.hal_buffer(), Some(range.start)..Some(range.end))
 	}
 
+	/// Splits the view into `[0, element)` and `[element, len)`. The view is
+	/// consumed so the two halves can not overlap with it. For uniform views
+	/// the second half must start on `min_uniform_buffer_offset_alignment`.
+	pub fn split_at(self, element: usize) -> (BufferView<'a, T>, BufferView<'a, T>) {
+		assert!(
+			element <= self.element_count(),
+			"Split point {} is out of bounds for a view of {} elements",
+			element,
+			self.element_count()
+		);
+		let element = element as buffer::Offset;
+		let mut front = self.desc;
+		front.len = element;
+		front.padding = 0;
+		let mut back = self.desc;
+		back.len -= element;
+		back.offset += element * self.desc.type_size;
+		if self.desc.usage.contains(Usage::UNIFORM) {
+			let align = self
+				.buffer()
+				.data()
+				.limits()
+				.min_uniform_buffer_offset_alignment;
+			assert_eq!(
+				back.offset % align,
+				0,
+				"Splitting at element {} puts the second half at offset {}, which is not a \
+				 multiple of the uniform buffer offset alignment {}",
+				element,
+				back.offset,
+				align
+			);
+		}
+		(
+			BufferView {
+				buffer: self.buffer.clone(),
+				desc: front,
+				phantom: PhantomData,
+			},
+			BufferView {
+				buffer: self.buffer,
+				desc: back,
+				phantom: PhantomData,
+			},
+		)
+	}
+
+	pub fn element_count(&self) -> usize { self.desc.len as usize }
+
+	pub fn padding_bytes(&self) -> u64 { self.desc.padding }
+
 	pub(crate) fn size(&self) -> buffer::Offset { self.desc.type_size * self.desc.len }
 
 	pub(crate) fn hal_buffer(&self) -> &<Backend as gfx_hal::Backend>::Buffer {
@@ -256,7 +457,8 @@ impl<'a> Buffer<'a> for CPUBuffer<'a> {
 			data,
 			descs,
 			Usage::empty(),
-			Properties::COHERENT | Properties::CPU_VISIBLE,
+			Properties::CPU_VISIBLE,
+			Properties::COHERENT,
 		);
 		BufferView::fold_descs(Arc::new(CPUBuffer(base)), descs, sizes)
 	}
@@ -274,19 +476,101 @@ impl<'a> BufferView<'a, CPUBuffer<'a>> {
 		unsafe {
 			let memory = self.buffer.0.block.get_ref().memory();
 
-			let map = device.map_memory(memory, range.clone()).unwrap();
+			let (map, mapped) = self.buffer.0.map_range(range);
 
 			std::ptr::copy_nonoverlapping(data.as_ptr(), map as *mut T, data.len());
+			self.buffer.0.flush(mapped);
+
+			device.unmap_memory(memory);
+		}
+	}
+
+	/// Writes `data` to the start of the view. `Pod` guarantees any bit pattern
+	/// is valid, so the element type of the view is not checked.
+	pub fn write_pod<T: Pod>(&self, data: &[T]) { self.write_pod_at(0, data) }
+
+	/// Writes `data` starting `offset` bytes into the view.
+	pub fn write_pod_at<T: Pod>(&self, offset: buffer::Offset, data: &[T]) {
+		let bytes: &[u8] = bytemuck::cast_slice(data);
+		assert!(
+			offset + bytes.len() as buffer::Offset <= self.size(),
+			"Writing {} bytes at offset {} overflows a view of {} bytes",
+			bytes.len(),
+			offset,
+			self.size()
+		);
+		let device = self.buffer.0.data.device();
+		let start = self.buffer.block().range().start + self.offset() + offset;
+		let range = start..start + bytes.len() as buffer::Offset;
+		unsafe {
+			let memory = self.buffer.0.block.get_ref().memory();
+			let (map, mapped) = self.buffer.0.map_range(range);
+			std::ptr::copy_nonoverlapping(bytes.as_ptr(), map, bytes.len());
+			self.buffer.0.flush(mapped);
+			device.unmap_memory(memory);
+		}
+	}
+
+	pub fn upload_from_iter<T: 'static + Copy, I: ExactSizeIterator<Item = T>>(
+		&self,
+		mut offset: buffer::Offset,
+		iter: I,
+	) {
+		let len = iter.len();
+		assert!(self.desc.len >= len as buffer::Offset);
+		assert_eq!(self.desc.type_id, TypeId::of::<T>());
+		let device = self.buffer.0.data.device();
+		let size_in_bytes = self.desc.type_size * (len as buffer::Offset);
+		offset += self.offset();
+		offset += self.buffer.block().range().start;
+		let range = offset..offset + size_in_bytes;
+		unsafe {
+			let memory = self.buffer.0.block.get_ref().memory();
+
+			let (map, mapped) = self.buffer.0.map_range(range);
+			let map = map as *mut T;
+
+			iter.take(len)
+				.enumerate()
+				.for_each(|(i, item)| std::ptr::write(map.add(i), item));
+			self.buffer.0.flush(mapped);
 
 			device.unmap_memory(memory);
 		}
 	}
 }
 
+pub struct UniformBuffer<'a, T: Copy + 'static> {
+	view: BufferView<'a, CPUBuffer<'a>>,
+	phantom: PhantomData<T>,
+}
+
+impl<'a, T: Copy + 'static> UniformBuffer<'a, T> {
+	pub fn create(data: &'a HALData) -> Self {
+		let desc = BufferViewDesc::create_desc::<T>(Usage::UNIFORM, 1);
+		let view = CPUBuffer::create(data, &[desc]).remove(0);
+		UniformBuffer {
+			view,
+			phantom: PhantomData,
+		}
+	}
+
+	pub fn write(&self, value: &T) { self.view.upload(0, slice::from_ref(value)) }
+
+	pub fn descriptor(&self) -> Descriptor<Backend> { self.view.descriptor() }
+
+	pub fn view(&self) -> &BufferView<'a, CPUBuffer<'a>> { &self.view }
+}
+
 impl<'a> Buffer<'a> for GPUBuffer<'a> {
 	fn create<'b>(data: &'a HALData, descs: &'b [BufferViewDesc]) -> Vec<BufferView<'a, Self>> {
-		let (sizes, base) =
-			BaseBuffer::create_descs(data, descs, Usage::TRANSFER_DST, Properties::DEVICE_LOCAL);
+		let (sizes, base) = BaseBuffer::create_descs(
+			data,
+			descs,
+			Usage::TRANSFER_SRC | Usage::TRANSFER_DST,
+			Properties::DEVICE_LOCAL,
+			Properties::empty(),
+		);
 		BufferView::fold_descs(Arc::new(GPUBuffer(base)), descs, sizes)
 	}
 }
@@ -318,6 +602,50 @@ impl<'a> BufferView<'a, GPUBuffer<'a>> {
 			);
 		})
 	}
+
+	/// Copies `element_range` back through `staging` and blocks until the data
+	/// is on the CPU. This stalls the queue and is meant for debugging.
+	pub fn download<T: Copy + 'static>(
+		&self,
+		element_range: Range<usize>,
+		staging: &StagingBuffer,
+		command_pool: &CommandPool,
+		fence: &Fence,
+	) -> Vec<T> {
+		assert_eq!(self.desc.type_id, TypeId::of::<T>());
+		assert!(
+			element_range.start <= element_range.end && element_range.end <= self.element_count(),
+			"Download range {:?} is out of bounds for a view of {} elements",
+			element_range,
+			self.element_count()
+		);
+		let len = element_range.end - element_range.start;
+		let range = BufferCopy {
+			src: self.offset() + element_range.start as buffer::Offset * self.desc.type_size,
+			dst: 0,
+			size: len as buffer::Offset * self.desc.type_size,
+		};
+		assert!(
+			range.size <= staging.size(),
+			"Downloading {} bytes does not fit into the {} byte staging buffer",
+			range.size,
+			staging.size()
+		);
+		// Don't overwrite the staging memory while an upload still reads it.
+		staging.wait_on_upload();
+		fence.wait_n_reset();
+		command_pool.single_submit(&[], &[], fence, |buffer| unsafe {
+			buffer.copy_buffer(self.hal_buffer(), staging.base.buffer.get_ref(), &[range]);
+		});
+		fence.wait();
+		staging.read(len)
+	}
+}
+
+impl<'a, I: IndexType + 'static> IndexBuffer<'a, I> {
+	pub fn count(&self) -> u32 { self.view.element_count() as u32 }
+
+	pub fn view(&self) -> &BufferView<'a, GPUBuffer<'a>> { &self.view }
 }
 
 impl<'a> StagingBuffer<'a> {
@@ -327,12 +655,12 @@ impl<'a> StagingBuffer<'a> {
 		size: buffer::Offset,
 	) -> StagingBuffer<'a> {
 		let fence = data.create_fence();
-		fence.reset();
 		StagingBuffer {
 			base: BaseBuffer::create(
 				data,
-				Usage::TRANSFER_SRC,
-				Properties::COHERENT | Properties::CPU_VISIBLE,
+				Usage::TRANSFER_SRC | Usage::TRANSFER_DST,
+				Properties::CPU_VISIBLE,
+				Properties::COHERENT,
 				size,
 			),
 			command_pool,
@@ -352,15 +680,83 @@ impl<'a> StagingBuffer<'a> {
 		let memory = self.base.block().memory();
 		self.fence.wait_n_reset();
 		unsafe {
-			let map = device.map_memory(memory, range.clone()).unwrap();
+			let (map, mapped) = self.base.map_range(range);
 
 			std::ptr::copy_nonoverlapping(data.as_ptr(), map as *mut T, data.len());
+			self.base.flush(mapped);
+
+			device.unmap_memory(memory);
+		}
+	}
+
+	pub(crate) fn read<T: Copy>(&self, len: usize) -> Vec<T> {
+		let size_in_bytes = (size_of::<T>() * len) as buffer::Offset;
+		assert!(
+			self.base.size_in_bytes >= size_in_bytes,
+			"Attempted to read more data than the buffer could hold!"
+		);
+		let device = self.base.data.device();
+		let offset = self.base.block().range().start;
+		let range = offset..offset + size_in_bytes;
+		let memory = self.base.block().memory();
+		let mut out = Vec::with_capacity(len);
+		unsafe {
+			let (map, mapped) = self.base.map_range(range);
+			self.base.invalidate(mapped);
+
+			std::ptr::copy_nonoverlapping(map as *const T, out.as_mut_ptr(), len);
+			out.set_len(len);
 
 			device.unmap_memory(memory);
 		}
+		out
 	}
 
 	pub fn wait_on_upload(&self) { self.fence.wait() }
+
+	pub(crate) fn size(&self) -> buffer::Offset { self.base.size_in_bytes }
+}
+
+impl<'a> BufferPool<'a> {
+	pub(crate) fn create(
+		data: &'a HALData,
+		command_pool: &'a CommandPool<'a>,
+		staging_size: buffer::Offset,
+	) -> BufferPool<'a> {
+		BufferPool {
+			data,
+			command_pool,
+			staging: StagingBuffer::create(data, command_pool, staging_size),
+		}
+	}
+
+	pub fn create_buffers<'b, T: Buffer<'a>>(
+		&self,
+		descs: &'b [BufferViewDesc],
+	) -> Vec<BufferView<'a, T>> {
+		T::create(self.data, descs)
+	}
+
+	/// Creates a device local index buffer and uploads `indices` through the
+	/// pool's staging buffer.
+	pub fn create_index_buffer<I: IndexType + 'static>(&self, indices: &[I]) -> IndexBuffer<'a, I> {
+		let desc = BufferViewDesc::create_desc::<I>(Usage::INDEX, indices.len() as _);
+		let view = GPUBuffer::create(self.data, &[desc]).remove(0);
+		view.staged_upload(0, indices, &self.staging);
+		IndexBuffer {
+			view,
+			phantom: PhantomData,
+		}
+	}
+
+	pub fn staging_buffer(&self) -> &StagingBuffer<'a> { &self.staging }
+
+	pub fn staging_buffer_size(&self) -> u64 { self.staging.size() }
+
+	pub fn resize_staging(&mut self, new_size: u64) {
+		self.staging.wait_on_upload();
+		self.staging = StagingBuffer::create(self.data, self.command_pool, new_size);
+	}
 }
 
 macro_rules! impl_inner {
@@ -382,3 +778,24 @@ macro_rules! impl_inner {
 impl_inner!(GPUBuffer, 0);
 impl_inner!(CPUBuffer, 0);
 impl_inner!(StagingBuffer, base);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn uniform_array_ranges_do_not_overlap() {
+		let desc = BufferViewDesc::create_desc::<[f32; 4]>(Usage::UNIFORM, 16);
+		let first = desc.byte_range(0..8);
+		let second = desc.byte_range(8..16);
+		assert_eq!(first, 0..128);
+		assert_eq!(second, 128..256);
+		assert!(first.end <= second.start);
+	}
+
+	#[test]
+	#[should_panic]
+	fn range_past_the_end_panics() {
+		BufferViewDesc::create_desc::<[f32; 4]>(Usage::UNIFORM, 16).byte_range(8..17);
+	}
+}
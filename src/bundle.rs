@@ -0,0 +1,70 @@
+use crate::{
+	CommandPool,
+	Fence,
+	HALData,
+	Semaphore,
+	Texture,
+};
+
+/// Owns resources that only borrow the `HALData` and destroys them in a fixed
+/// order once the device is idle: semaphores, fences, textures and finally
+/// command pools. Resources that borrow other resources (pipelines,
+/// framebuffers, descriptor pools) must still be dropped before the bundle.
+pub struct ResourceBundle<'a> {
+	data: &'a HALData,
+	semaphores: Vec<Semaphore<'a>>,
+	fences: Vec<Fence<'a>>,
+	textures: Vec<Texture<'a>>,
+	command_pools: Vec<CommandPool<'a>>,
+}
+
+impl<'a> ResourceBundle<'a> {
+	pub fn create(data: &'a HALData) -> ResourceBundle<'a> {
+		ResourceBundle {
+			data,
+			semaphores: Vec::new(),
+			fences: Vec::new(),
+			textures: Vec::new(),
+			command_pools: Vec::new(),
+		}
+	}
+
+	pub fn add_semaphore(&mut self, semaphore: Semaphore<'a>) -> usize {
+		self.semaphores.push(semaphore);
+		self.semaphores.len() - 1
+	}
+
+	pub fn add_fence(&mut self, fence: Fence<'a>) -> usize {
+		self.fences.push(fence);
+		self.fences.len() - 1
+	}
+
+	pub fn add_texture(&mut self, texture: Texture<'a>) -> usize {
+		self.textures.push(texture);
+		self.textures.len() - 1
+	}
+
+	pub fn add_command_pool(&mut self, command_pool: CommandPool<'a>) -> usize {
+		self.command_pools.push(command_pool);
+		self.command_pools.len() - 1
+	}
+
+	pub fn semaphore(&self, idx: usize) -> &Semaphore<'a> { &self.semaphores[idx] }
+
+	pub fn fence(&self, idx: usize) -> &Fence<'a> { &self.fences[idx] }
+
+	pub fn texture(&self, idx: usize) -> &Texture<'a> { &self.textures[idx] }
+
+	pub fn command_pool(&self, idx: usize) -> &CommandPool<'a> { &self.command_pools[idx] }
+}
+
+impl<'a> Drop for ResourceBundle<'a> {
+	fn drop(&mut self) {
+		self.data.wait_idle();
+		self.semaphores.clear();
+		self.fences.clear();
+		self.textures.clear();
+		self.command_pools.clear();
+		println!("Dropped ResourceBundle");
+	}
+}
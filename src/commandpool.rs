@@ -1,39 +1,78 @@
 use std::{
 	cell::RefCell,
-	iter::once,
+	iter::{
+		empty,
+		once,
+	},
 	mem::MaybeUninit,
+	ops::Range,
+	time::Duration,
 };
 
 use gfx_hal::{
 	command::{
+		BufferImageCopy,
 		CommandBuffer,
+		CommandBufferInheritanceInfo,
 		OneShot,
 		Primary,
+		Secondary,
+	},
+	format::Aspects,
+	image::{
+		Access,
+		Extent,
+		Layout,
+		Offset,
+		SubresourceLayers,
+		SubresourceRange,
+	},
+	memory::{
+		Barrier,
+		Dependencies,
 	},
 	pool::{
 		CommandPool as HAL_CommandPool,
 		CommandPoolCreateFlags,
 	},
 	pso::PipelineStage,
+	query::Type as QueryType,
 	Device,
 	Graphics,
 	Submission,
 };
 
 use crate::{
+	buffer::inner::InnerBuffer,
 	gfx_back::Backend,
 	util::TakeExt,
+	Event,
 	Fence,
+	FencePool,
+	FrameBuffer,
 	HALData,
+	QueryPool,
+	RenderPass,
 	Semaphore,
+	Texture,
 };
 
+pub type SecondaryBuffer = CommandBuffer<Backend, Graphics, OneShot, Secondary>;
+
 pub struct CommandPool<'a> {
 	pub(crate) data: &'a HALData,
 	pub(crate) pool: MaybeUninit<RefCell<HAL_CommandPool<Backend, Graphics>>>,
 	buffers: RefCell<Vec<CommandBuffer<Backend, Graphics, OneShot, Primary>>>,
 }
 
+/// A submission bracketed by timestamp queries, returned by
+/// `CommandPool::timed_submit`.
+pub struct TimedSubmit<'a, 'f> {
+	queries: QueryPool<'a>,
+	fence: &'f Fence<'f>,
+	period: f32,
+}
+
 impl<'a> CommandPool<'a> {
 	pub(crate) fn create(data: &HALData) -> CommandPool {
 		println!("Creating Commandpool");
@@ -62,6 +101,21 @@ impl<'a> CommandPool<'a> {
 		}
 	}
 
+	/// Waits for the last submission signalling `fence` before resetting, so
+	/// no buffer is freed while the GPU still executes it.
+	pub fn reset_when_idle(&self, fence: &Fence) {
+		fence.wait();
+		self.reset();
+	}
+
+	/// Returns unused pool memory to the system without touching recorded
+	/// buffers.
+	pub fn trim(&self) {
+		unsafe {
+			self.pool.get_ref().borrow_mut().trim();
+		}
+	}
+
 	pub fn single_submit(
 		&self,
 		wait_sems: &[(&Semaphore, PipelineStage)],
@@ -92,6 +146,207 @@ impl<'a> CommandPool<'a> {
 			self.buffers.borrow_mut().push(buffer);
 		}
 	}
+
+	/// Records a secondary buffer continuing `subpass` of `pass` on
+	/// `frame_idx` of `framebuffer`, to be run with `execute_commands` inside
+	/// that subpass when it was begun with secondary contents. The pool is not
+	/// `Sync`, so recording on several threads needs one `CommandPool` per
+	/// thread.
+	pub fn record_secondary(
+		&self,
+		pass: &RenderPass,
+		subpass: usize,
+		framebuffer: &FrameBuffer,
+		frame_idx: usize,
+		f: impl FnOnce(&mut SecondaryBuffer),
+	) -> SecondaryBuffer {
+		assert!(
+			subpass < pass.subpass_count(),
+			"The render pass has no subpass {}",
+			subpass
+		);
+		unsafe {
+			let mut buffer = self
+				.pool
+				.get_ref()
+				.borrow_mut()
+				.acquire_secondary_command_buffer::<OneShot>();
+			buffer.begin(CommandBufferInheritanceInfo {
+				subpass: Some(pass.make_subpass(subpass)),
+				framebuffer: Some(&framebuffer[frame_idx]),
+				..Default::default()
+			});
+			f(&mut buffer);
+			buffer.finish();
+			buffer
+		}
+	}
+
+	/// Submits a compute dispatch of `workgroups` on the graphics queue, which
+	/// always supports compute. `bind` records the compute pipeline and its
+	/// descriptor sets before the dispatch.
+	pub fn dispatch_compute(
+		&self,
+		workgroups: [u32; 3],
+		fence: &Fence,
+		bind: impl FnOnce(&mut CommandBuffer<Backend, Graphics, OneShot, Primary>),
+	) {
+		fence.wait_n_reset();
+		self.single_submit(&[], &[], fence, |buffer| {
+			bind(buffer);
+			unsafe { buffer.dispatch(workgroups) }
+		});
+	}
+
+	/// Submits like `single_submit`, signaling the next fence of `fences`
+	/// instead of a caller supplied one. Wait on the returned fence to know
+	/// when the work is done.
+	pub fn pooled_submit<'f>(
+		&self,
+		wait_sems: &[(&Semaphore, PipelineStage)],
+		signal_sems: &[&Semaphore],
+		fences: &'f FencePool<'f>,
+		f: impl FnOnce(&mut CommandBuffer<Backend, Graphics, OneShot, Primary>),
+	) -> &'f Fence<'f> {
+		let fence = fences.acquire();
+		self.single_submit(wait_sems, signal_sems, fence, f);
+		fence
+	}
+
+	/// Copies tightly packed texels from the start of `src_buffer` into one
+	/// mip level and layer of a sampled texture, which is moved out of and back
+	/// into `ShaderReadOnlyOptimal` around the copy.
+	pub fn copy_buffer_to_image(
+		&self,
+		src_buffer: &impl InnerBuffer,
+		dst_image: &Texture,
+		mip: u8,
+		layer: u16,
+		extent: Extent,
+		fence: &Fence,
+	) {
+		assert!(
+			mip < dst_image.mip_levels && layer < dst_image.layers,
+			"Mip {} layer {} is out of range for the texture",
+			mip,
+			layer
+		);
+		let image = dst_image.image();
+		fence.wait_n_reset();
+		self.single_submit(&[], &[], fence, |cmd_buf| {
+			Texture::transition_image_layout(
+				cmd_buf,
+				image,
+				mip,
+				layer..layer + 1,
+				Layout::ShaderReadOnlyOptimal..Layout::TransferDstOptimal,
+			);
+			let copy = BufferImageCopy {
+				buffer_offset: 0,
+				buffer_width: 0,
+				buffer_height: 0,
+				image_layers: SubresourceLayers {
+					aspects: Aspects::COLOR,
+					level: mip,
+					layers: layer..layer + 1,
+				},
+				image_offset: Offset::ZERO,
+				image_extent: extent,
+			};
+			unsafe {
+				cmd_buf.copy_buffer_to_image(
+					src_buffer.hal_buffer(),
+					image,
+					Layout::TransferDstOptimal,
+					once(copy),
+				);
+			}
+			Texture::transition_image_layout(
+				cmd_buf,
+				image,
+				mip,
+				layer..layer + 1,
+				Layout::TransferDstOptimal..Layout::ShaderReadOnlyOptimal,
+			);
+		});
+	}
+
+	/// Makes compute shader writes to `image`, which must be in the `General`
+	/// layout, visible to fragment shaders and moves it to
+	/// `ShaderReadOnlyOptimal` for sampling.
+	pub fn compute_to_graphics_barrier(&self, image: &Texture, fence: &Fence) {
+		let barrier = Barrier::Image {
+			states: (Access::SHADER_WRITE, Layout::General)..
+				(Access::SHADER_READ, Layout::ShaderReadOnlyOptimal),
+			target: image.image(),
+			families: None,
+			range: SubresourceRange {
+				aspects: Aspects::COLOR,
+				levels: 0..image.mip_levels,
+				layers: 0..image.layers,
+			},
+		};
+		fence.wait_n_reset();
+		self.single_submit(&[], &[], fence, |cmd_buf| unsafe {
+			cmd_buf.pipeline_barrier(
+				PipelineStage::COMPUTE_SHADER..PipelineStage::FRAGMENT_SHADER,
+				Dependencies::empty(),
+				once(&barrier),
+			);
+		});
+	}
+
+	/// Records setting `event` once the commands before it reach `stage`.
+	pub fn signal_event(
+		cmd_buf: &mut CommandBuffer<Backend, Graphics, OneShot, Primary>,
+		event: &Event,
+		stage: PipelineStage,
+	) {
+		unsafe { cmd_buf.set_event(event.event(), stage) }
+	}
+
+	/// Records a wait for `event`, blocking `stages.end` of the following
+	/// commands until the event has been set at `stages.start`.
+	pub fn wait_event(
+		cmd_buf: &mut CommandBuffer<Backend, Graphics, OneShot, Primary>,
+		event: &Event,
+		stages: Range<PipelineStage>,
+	) {
+		unsafe { cmd_buf.wait_events(once(event.event()), stages, empty::<Barrier<Backend>>()) }
+	}
+
+	/// Like `single_submit`, but records timestamps before and after `f` so
+	/// the GPU execution time can be read back with
+	/// `TimedSubmit::wait_and_read`.
+	pub fn timed_submit<'f>(
+		&self,
+		wait_sems: &[(&Semaphore, PipelineStage)],
+		signal_sems: &[&Semaphore],
+		fence: &'f Fence<'f>,
+		f: impl FnOnce(&mut CommandBuffer<Backend, Graphics, OneShot, Primary>),
+	) -> TimedSubmit<'a, 'f> {
+		let queries = QueryPool::create(self.data, QueryType::Timestamp, 2);
+		self.single_submit(wait_sems, signal_sems, fence, |buffer| unsafe {
+			buffer.reset_query_pool(queries.pool(), 0..2);
+			buffer.write_timestamp(PipelineStage::TOP_OF_PIPE, queries.query(0));
+			f(buffer);
+			buffer.write_timestamp(PipelineStage::BOTTOM_OF_PIPE, queries.query(1));
+		});
+		TimedSubmit {
+			queries,
+			fence,
+			period: self.data.limits().timestamp_period,
+		}
+	}
+}
+
+impl<'a, 'f> TimedSubmit<'a, 'f> {
+	pub fn wait_and_read(&self) -> Duration {
+		self.fence.wait();
+		let timestamps = self.queries.read_results(0..2, 1);
+		let ticks = timestamps[1].saturating_sub(timestamps[0]);
+		Duration::from_nanos((ticks as f64 * self.period as f64) as u64)
+	}
 }
 
 impl<'a> Drop for CommandPool<'a> {
@@ -0,0 +1,97 @@
+use cgmath::{
+	Matrix,
+	Matrix4,
+	Vector4,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingBox {
+	pub min: [f32; 3],
+	pub max: [f32; 3],
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Frustum {
+	planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+	/// Extracts the six clip planes from a view-projection matrix. Depth is
+	/// expected in the `[0, 1]` range used by gfx-hal.
+	pub fn from_matrix(mvp: &Matrix4<f32>) -> Frustum {
+		let (r0, r1, r2, r3) = (mvp.row(0), mvp.row(1), mvp.row(2), mvp.row(3));
+		Frustum {
+			planes: [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2],
+		}
+	}
+
+	pub fn planes(&self) -> &[Vector4<f32>; 6] { &self.planes }
+}
+
+impl BoundingBox {
+	pub fn is_visible(&self, frustum: &Frustum) -> bool {
+		frustum.planes.iter().all(|plane| {
+			let positive = [
+				if plane.x >= 0.0 {
+					self.max[0]
+				} else {
+					self.min[0]
+				},
+				if plane.y >= 0.0 {
+					self.max[1]
+				} else {
+					self.min[1]
+				},
+				if plane.z >= 0.0 {
+					self.max[2]
+				} else {
+					self.min[2]
+				},
+			];
+			plane.x * positive[0] + plane.y * positive[1] + plane.z * positive[2] + plane.w >= 0.0
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use cgmath::{
+		perspective,
+		Deg,
+		SquareMatrix,
+	};
+
+	use super::*;
+
+	fn bounds(min: [f32; 3], max: [f32; 3]) -> BoundingBox { BoundingBox { min, max } }
+
+	#[test]
+	fn identity_planes_bound_the_clip_volume() {
+		let frustum = Frustum::from_matrix(&Matrix4::identity());
+		// x >= -1, x <= 1, y >= -1, y <= 1, z >= 0 and z <= 1.
+		assert_eq!(frustum.planes()[0], Vector4::new(1.0, 0.0, 0.0, 1.0));
+		assert_eq!(frustum.planes()[1], Vector4::new(-1.0, 0.0, 0.0, 1.0));
+		assert_eq!(frustum.planes()[4], Vector4::new(0.0, 0.0, 1.0, 0.0));
+		assert_eq!(frustum.planes()[5], Vector4::new(0.0, 0.0, -1.0, 1.0));
+	}
+
+	#[test]
+	fn identity_inside_outside_and_straddling() {
+		let frustum = Frustum::from_matrix(&Matrix4::identity());
+		assert!(bounds([-0.5, -0.5, 0.2], [0.5, 0.5, 0.8]).is_visible(&frustum));
+		assert!(!bounds([2.0, -0.5, 0.2], [3.0, 0.5, 0.8]).is_visible(&frustum));
+		assert!(!bounds([-0.5, -0.5, -2.0], [0.5, 0.5, -1.0]).is_visible(&frustum));
+		assert!(bounds([0.5, 0.5, 0.5], [1.5, 1.5, 1.5]).is_visible(&frustum));
+	}
+
+	#[test]
+	fn perspective_inside_outside_and_straddling() {
+		let frustum = Frustum::from_matrix(&perspective(Deg(90.0), 1.0, 0.1, 100.0));
+		// The camera looks down -z.
+		assert!(bounds([-1.0, -1.0, -6.0], [1.0, 1.0, -4.0]).is_visible(&frustum));
+		assert!(!bounds([-1.0, -1.0, 4.0], [1.0, 1.0, 6.0]).is_visible(&frustum));
+		assert!(!bounds([10.0, -1.0, -6.0], [12.0, 1.0, -4.0]).is_visible(&frustum));
+		assert!(!bounds([-1.0, -1.0, -300.0], [1.0, 1.0, -200.0]).is_visible(&frustum));
+		assert!(bounds([4.0, -1.0, -6.0], [8.0, 1.0, -4.0]).is_visible(&frustum));
+	}
+}
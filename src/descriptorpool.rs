@@ -1,14 +1,15 @@
-use std::{
-	iter::once,
-	mem::MaybeUninit,
-};
+use std::iter::once;
 
 use gfx_hal::{
 	pso::{
 		Descriptor,
+		DescriptorBinding,
 		DescriptorPool as HALDescriptorPool,
 		DescriptorRangeDesc,
+		DescriptorSetCopy,
+		DescriptorSetLayoutBinding,
 		DescriptorSetWrite,
+		DescriptorType,
 	},
 	Device,
 };
@@ -22,7 +23,6 @@ use crate::{
 		UniformInfo,
 		VertexInfo,
 	},
-	util::TakeExt,
 };
 
 pub struct DescriptorPool<
@@ -33,8 +33,13 @@ pub struct DescriptorPool<
 	Constants: PushConstantInfo,
 > {
 	shader: &'a Shader<'a, Vertex, Uniforms, Index, Constants>,
-	descriptor_pool: MaybeUninit<<Backend as gfx_hal::Backend>::DescriptorPool>,
+	descriptor_pools: Vec<<Backend as gfx_hal::Backend>::DescriptorPool>,
 	descriptor_sets: Vec<<Backend as gfx_hal::Backend>::DescriptorSet>,
+	pool_count: usize,
+	allocated: usize,
+	/// Bindings of type `UniformBufferDynamic` or `StorageBufferDynamic`.
+	dynamic_bindings: Vec<DescriptorBinding>,
+	dynamic_offset_count: usize,
 }
 
 impl<
@@ -50,16 +55,40 @@ impl<
 		pool_count: usize,
 	) -> DescriptorPool<'a, Vertex, Uniforms, Index, Constants> {
 		println!("Creating Descriptors");
+		let (descriptor_pool, descriptor_sets) = Self::create_hal_pool(shader, pool_count);
+
+		let dynamic_bindings = shader
+			.bindings()
+			.iter()
+			.filter(|binding| is_dynamic(binding.ty))
+			.map(|binding| binding.binding)
+			.collect();
+
+		DescriptorPool {
+			shader,
+			descriptor_pools: vec![descriptor_pool],
+			descriptor_sets,
+			pool_count,
+			allocated: 0,
+			dynamic_bindings,
+			dynamic_offset_count: dynamic_offset_count(shader.bindings()),
+		}
+	}
+
+	fn create_hal_pool(
+		shader: &'a Shader<'a, Vertex, Uniforms, Index, Constants>,
+		pool_count: usize,
+	) -> (
+		<Backend as gfx_hal::Backend>::DescriptorPool,
+		Vec<<Backend as gfx_hal::Backend>::DescriptorSet>,
+	) {
 		let device = shader.data.device();
 		let desc_layout = shader.desc_layout();
 		let mut descriptor_pool = {
-			let descriptors = shader
-				.layout_bindings()
-				.iter()
-				.map(|uniform| DescriptorRangeDesc {
-					ty: uniform.ty,
-					count: pool_count,
-				});
+			let descriptors = shader.bindings().iter().map(|uniform| DescriptorRangeDesc {
+				ty: uniform.ty,
+				count: uniform.count * pool_count,
+			});
 			unsafe {
 				device
 					.create_descriptor_pool(pool_count, descriptors)
@@ -76,15 +105,29 @@ impl<
 			};
 			buf
 		};
+		(descriptor_pool, descriptor_sets)
+	}
 
-		DescriptorPool {
-			shader,
-			descriptor_pool: MaybeUninit::new(descriptor_pool),
-			descriptor_sets,
+	/// Hands out the next unused set, creating another pool of the original
+	/// size when every set is taken.
+	pub fn alloc_set(&mut self) -> usize {
+		if self.allocated == self.descriptor_sets.len() {
+			println!("Growing Descriptors");
+			let (pool, sets) = Self::create_hal_pool(self.shader, self.pool_count);
+			self.descriptor_pools.push(pool);
+			self.descriptor_sets.extend(sets);
 		}
+		self.allocated += 1;
+		self.allocated - 1
 	}
 
+	pub fn allocated(&self) -> usize { self.allocated }
+
+	pub fn capacity(&self) -> usize { self.descriptor_sets.len() }
+
 	pub fn write(&self, set: usize, descriptor: &[Descriptor<Backend>]) {
+		#[cfg(debug_assertions)]
+		self.validate_writes(descriptor);
 		let device = self.shader.data.device();
 		let writes = descriptor
 			.iter()
@@ -98,9 +141,148 @@ impl<
 		unsafe { device.write_descriptor_sets(writes) }
 	}
 
+	/// Like calling `write` for every entry, but with a single driver call.
+	pub fn write_batch(&self, writes: &[(usize, &[Descriptor<Backend>])]) {
+		#[cfg(debug_assertions)]
+		for (_, descriptors) in writes {
+			self.validate_writes(descriptors);
+		}
+		let device = self.shader.data.device();
+		let writes = writes.iter().flat_map(|(set, descriptors)| {
+			descriptors
+				.iter()
+				.enumerate()
+				.map(move |(binding, desc)| DescriptorSetWrite {
+					set: self.descriptor_set(*set),
+					binding: binding as u32,
+					array_offset: 0,
+					descriptors: once(desc),
+				})
+		});
+		unsafe { device.write_descriptor_sets(writes) }
+	}
+
+	/// Checks that `descriptor` has one descriptor per shader binding, each
+	/// of the binding's type.
+	#[cfg(debug_assertions)]
+	fn validate_writes(&self, descriptor: &[Descriptor<Backend>]) {
+		let bindings = self.shader.bindings();
+		assert_eq!(
+			descriptor.len(),
+			bindings.len(),
+			"Descriptor set writes need one descriptor per shader binding"
+		);
+		for (binding, desc) in bindings.iter().zip(descriptor) {
+			assert!(
+				matches_type(desc, binding.ty),
+				"Descriptor for binding {} does not match its type {:?}",
+				binding.binding,
+				binding.ty
+			);
+		}
+	}
+
+	pub fn write_array(
+		&self,
+		set: usize,
+		binding: u32,
+		array_offset: u32,
+		descriptors: &[Descriptor<Backend>],
+	) {
+		let layout_binding = self
+			.shader
+			.bindings()
+			.iter()
+			.find(|b| b.binding == binding)
+			.expect("Attempted to write to a binding the shader does not have");
+		assert!(
+			array_offset as usize + descriptors.len() <= layout_binding.count,
+			"Descriptor array write out of bounds"
+		);
+		let device = self.shader.data.device();
+		let write = DescriptorSetWrite {
+			set: self.descriptor_set(set),
+			binding,
+			array_offset: array_offset as usize,
+			descriptors,
+		};
+		unsafe { device.write_descriptor_sets(once(write)) }
+	}
+
+	/// Copies every binding of `src` into `dst`, e.g. to clone a material
+	/// before overriding one of its textures.
+	pub fn copy_set(&self, src: usize, dst: usize) {
+		assert!(
+			src < self.capacity() && dst < self.capacity(),
+			"Descriptor set copy {} -> {} is out of bounds for a pool of {} sets",
+			src,
+			dst,
+			self.capacity()
+		);
+		let device = self.shader.data.device();
+		let copies = self
+			.shader
+			.bindings()
+			.iter()
+			.map(|binding| DescriptorSetCopy {
+				src_set: self.descriptor_set(src),
+				src_binding: binding.binding,
+				src_array_offset: 0,
+				dst_set: self.descriptor_set(dst),
+				dst_binding: binding.binding,
+				dst_array_offset: 0,
+				count: binding.count,
+			});
+		unsafe { device.copy_descriptor_sets(copies) }
+	}
+
 	pub fn descriptor_set(&self, idx: usize) -> &<Backend as gfx_hal::Backend>::DescriptorSet {
 		&self.descriptor_sets[idx]
 	}
+
+	pub fn dynamic_bindings(&self) -> &[DescriptorBinding] { &self.dynamic_bindings }
+
+	/// Number of dynamic offsets binding a set of this pool takes: one per
+	/// array element of every dynamic binding.
+	pub fn dynamic_offset_count(&self) -> usize { self.dynamic_offset_count }
+}
+
+pub(crate) fn dynamic_offset_count(bindings: &[DescriptorSetLayoutBinding]) -> usize {
+	bindings
+		.iter()
+		.filter(|binding| is_dynamic(binding.ty))
+		.map(|binding| binding.count)
+		.sum()
+}
+
+pub(crate) fn is_dynamic(ty: DescriptorType) -> bool {
+	match ty {
+		DescriptorType::UniformBufferDynamic | DescriptorType::StorageBufferDynamic => true,
+		_ => false,
+	}
+}
+
+#[cfg(debug_assertions)]
+fn matches_type(desc: &Descriptor<Backend>, ty: DescriptorType) -> bool {
+	match desc {
+		Descriptor::Sampler(_) => ty == DescriptorType::Sampler,
+		Descriptor::Image(..) => match ty {
+			DescriptorType::SampledImage |
+			DescriptorType::StorageImage |
+			DescriptorType::InputAttachment => true,
+			_ => false,
+		},
+		Descriptor::CombinedImageSampler(..) => ty == DescriptorType::CombinedImageSampler,
+		Descriptor::Buffer(..) => match ty {
+			DescriptorType::UniformBuffer |
+			DescriptorType::StorageBuffer |
+			DescriptorType::UniformBufferDynamic |
+			DescriptorType::StorageBufferDynamic => true,
+			_ => false,
+		},
+		Descriptor::UniformTexelBuffer(_) => ty == DescriptorType::UniformTexelBuffer,
+		Descriptor::StorageTexelBuffer(_) => ty == DescriptorType::StorageTexelBuffer,
+	}
 }
 
 impl<
@@ -113,11 +295,10 @@ impl<
 {
 	fn drop(&mut self) {
 		let device = self.shader.data.device();
-		let pool = MaybeUninit::take(&mut self.descriptor_pool);
 		//        pool.free_sets(self.descriptor_sets.drain(..));
-		unsafe {
-			device.destroy_descriptor_pool(pool);
-		}
+		self.descriptor_pools
+			.drain(..)
+			.for_each(|pool| unsafe { device.destroy_descriptor_pool(pool) });
 		println!("Dropped Descriptors");
 	}
 }
@@ -0,0 +1,55 @@
+use std::mem::MaybeUninit;
+
+use gfx_hal::Device;
+
+use crate::{
+	gfx_back::Backend,
+	util::TakeExt,
+	HALData,
+};
+
+/// A GPU event, set and waited on from command buffers recorded through
+/// `CommandPool::signal_event` and `CommandPool::wait_event`.
+pub struct Event<'a> {
+	data: &'a HALData,
+	event: MaybeUninit<<Backend as gfx_hal::Backend>::Event>,
+}
+
+impl<'a> Event<'a> {
+	pub(crate) fn create(data: &'a HALData) -> Event<'a> {
+		println!("Creating Event");
+		let event = data.device().create_event().unwrap();
+		Event {
+			data,
+			event: MaybeUninit::new(event),
+		}
+	}
+
+	pub fn set(&self) {
+		unsafe {
+			self.data.device().set_event(self.event()).unwrap();
+		}
+	}
+
+	pub fn reset(&self) {
+		unsafe {
+			self.data.device().reset_event(self.event()).unwrap();
+		}
+	}
+
+	pub fn is_set(&self) -> bool {
+		unsafe { self.data.device().get_event_status(self.event()).unwrap() }
+	}
+
+	pub fn event(&self) -> &<Backend as gfx_hal::Backend>::Event { unsafe { self.event.get_ref() } }
+}
+
+impl<'a> Drop for Event<'a> {
+	fn drop(&mut self) {
+		let device = self.data.device();
+		unsafe {
+			device.destroy_event(MaybeUninit::take(&mut self.event));
+		}
+		println!("Dropped Event")
+	}
+}
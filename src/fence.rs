@@ -1,4 +1,7 @@
-use std::mem::MaybeUninit;
+use std::{
+	cell::Cell,
+	mem::MaybeUninit,
+};
 
 use gfx_hal::Device;
 
@@ -8,9 +11,26 @@ use crate::{
 	HALData,
 };
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FenceStatus {
+	Signaled,
+	Unsignaled,
+}
+
+/// Identifies one use of a fence between two resets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FenceVersion(pub u64);
+
 pub struct Fence<'a> {
 	data: &'a HALData,
 	fence: MaybeUninit<<Backend as gfx_hal::Backend>::Fence>,
+	version: Cell<u64>,
+}
+
+/// A ring of fences reused for frequent submissions.
+pub struct FencePool<'a> {
+	fences: Vec<Fence<'a>>,
+	next: Cell<usize>,
 }
 
 impl<'a> Fence<'a> {
@@ -20,6 +40,7 @@ impl<'a> Fence<'a> {
 		Fence {
 			data,
 			fence: MaybeUninit::new(fence),
+			version: Cell::new(0),
 		}
 	}
 
@@ -28,6 +49,23 @@ impl<'a> Fence<'a> {
 		unsafe {
 			self.data.device().reset_fence(fence).unwrap();
 		}
+		self.version.update(|v| v + 1);
+	}
+
+	/// The version of the submission currently using this fence.
+	pub fn version(&self) -> FenceVersion { FenceVersion(self.version.get()) }
+
+	pub fn wait_and_get_version(&self) -> FenceVersion {
+		self.wait();
+		self.version()
+	}
+
+	/// Whether the submission tagged with `version` has completed. Older
+	/// versions count as complete, since fences are only reset after being
+	/// waited on (see `wait_n_reset`).
+	pub fn is_version_signaled(&self, version: FenceVersion) -> bool {
+		let current = self.version();
+		version < current || (version == current && self.status() == FenceStatus::Signaled)
 	}
 
 	pub fn wait(&self) {
@@ -37,6 +75,16 @@ impl<'a> Fence<'a> {
 		}
 	}
 
+	pub fn status(&self) -> FenceStatus {
+		let fence = self.fence();
+		let signaled = unsafe { self.data.device().get_fence_status(fence).unwrap() };
+		if signaled {
+			FenceStatus::Signaled
+		} else {
+			FenceStatus::Unsignaled
+		}
+	}
+
 	pub fn wait_n_reset(&self) {
 		self.wait();
 		self.reset();
@@ -49,6 +97,31 @@ impl<'a> Fence<'a> {
 	}
 }
 
+impl<'a> FencePool<'a> {
+	pub fn create(data: &'a HALData, capacity: usize) -> FencePool<'a> {
+		assert!(capacity > 0, "A fence pool needs at least one fence");
+		FencePool {
+			fences: (0..capacity).map(|_| Fence::create(data)).collect(),
+			next: Cell::new(0),
+		}
+	}
+
+	/// Returns the next fence in the ring in the reset state, waiting for its
+	/// previous submission if that is still in flight.
+	pub fn acquire(&self) -> &Fence<'a> {
+		let idx = self.next.get();
+		self.next.set((idx + 1) % self.fences.len());
+		let fence = &self.fences[idx];
+		fence.wait_n_reset();
+		fence
+	}
+
+	/// Waits for every submission made with a fence from this pool.
+	pub fn release_all(&self) { self.fences.iter().for_each(Fence::wait) }
+
+	pub fn capacity(&self) -> usize { self.fences.len() }
+}
+
 impl<'a> Drop for Fence<'a> {
 	fn drop(&mut self) {
 		let device = self.data.device();
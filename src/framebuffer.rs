@@ -1,8 +1,17 @@
 use std::ops::Index;
 
 use gfx_hal::{
+	command::{
+		ClearValue,
+		CommandBuffer,
+		OneShot,
+		Primary,
+		RenderPassInlineEncoder,
+	},
 	image::Extent,
+	pso::Rect,
 	Device,
+	Graphics,
 };
 
 use crate::{
@@ -14,6 +23,10 @@ use crate::{
 pub struct FrameBuffer<'a> {
 	pass: &'a RenderPass<'a>,
 	frames: Vec<<Backend as gfx_hal::Backend>::Framebuffer>,
+	sizes: Vec<Extent>,
+	attachment_counts: Vec<usize>,
+	/// Views only this framebuffer uses, destroyed after it.
+	owned_views: Vec<ImageView<'a>>,
 }
 
 impl<'a> FrameBuffer<'a> {
@@ -23,7 +36,7 @@ impl<'a> FrameBuffer<'a> {
 		sizes: &'b [Extent],
 	) -> FrameBuffer<'a> {
 		println!("Creating Framebuffer");
-		let device = pass.swapchain.data.device();
+		let device = pass.data.device();
 		let frames = views
 			.iter()
 			.enumerate()
@@ -33,7 +46,25 @@ impl<'a> FrameBuffer<'a> {
 					.unwrap()
 			})
 			.collect::<Vec<_>>();
-		FrameBuffer { pass, frames }
+		FrameBuffer {
+			pass,
+			frames,
+			sizes: sizes[..views.len()].to_vec(),
+			attachment_counts: views.iter().map(Vec::len).collect(),
+			owned_views: Vec::new(),
+		}
+	}
+
+	/// A single frame rendering into `view`, which is kept alive for as long
+	/// as the framebuffer.
+	pub(crate) fn from_owned_view(
+		pass: &'a RenderPass,
+		view: ImageView<'a>,
+		size: Extent,
+	) -> FrameBuffer<'a> {
+		let mut fb = Self::create(pass, &[vec![&view]], &[size]);
+		fb.owned_views.push(view);
+		fb
 	}
 
 	pub(crate) fn create_same_size<'b>(
@@ -47,14 +78,16 @@ impl<'a> FrameBuffer<'a> {
 	pub(crate) fn from_swapchain(pass: &'a RenderPass) -> FrameBuffer<'a> {
 		//		#[cfg(not(feature = "gl"))]
 		let fb = {
-			let depth = &pass.swapchain.depth_tex;
-			let views = pass
+			let swapchain = pass
 				.swapchain
+				.expect("Only swapchain render passes have framebuffers from the chain");
+			let depth = &swapchain.depth_tex;
+			let views = swapchain
 				.image_views
 				.iter()
 				.map(|iv| vec![iv, depth.view()])
 				.collect::<Vec<_>>();
-			Self::create_same_size(pass, views.as_slice(), pass.swapchain.dims.clone())
+			Self::create_same_size(pass, views.as_slice(), swapchain.dims.clone())
 		};
 		//		#[cfg(feature = "gl")]
 		//		let fb = FrameBuffer {
@@ -65,6 +98,38 @@ impl<'a> FrameBuffer<'a> {
 	}
 }
 
+impl<'a> FrameBuffer<'a> {
+	/// Begins the render pass on `frame`, covering the whole framebuffer.
+	/// `clear_values` holds one value per attachment, in attachment order.
+	pub fn begin_render_pass<'c>(
+		&self,
+		cmd_buf: &'c mut CommandBuffer<Backend, Graphics, OneShot, Primary>,
+		frame: usize,
+		clear_values: &[ClearValue],
+	) -> RenderPassInlineEncoder<'c, Backend> {
+		assert_eq!(
+			clear_values.len(),
+			self.attachment_counts[frame],
+			"Every attachment of the framebuffer needs exactly one clear value"
+		);
+		let size = self.sizes[frame];
+		let area = Rect {
+			x: 0,
+			y: 0,
+			w: size.width as i16,
+			h: size.height as i16,
+		};
+		unsafe {
+			cmd_buf.begin_render_pass_inline(
+				self.pass.pass(),
+				&self.frames[frame],
+				area,
+				clear_values,
+			)
+		}
+	}
+}
+
 impl<'a> Index<usize> for FrameBuffer<'a> {
 	type Output = <Backend as gfx_hal::Backend>::Framebuffer;
 
@@ -73,7 +138,7 @@ impl<'a> Index<usize> for FrameBuffer<'a> {
 
 impl<'a> Drop for FrameBuffer<'a> {
 	fn drop(&mut self) {
-		let device = self.pass.swapchain.data.device();
+		let device = self.pass.data.device();
 		self.frames
 			.drain(..)
 			.for_each(|frame| unsafe { device.destroy_framebuffer(frame) });
@@ -1,7 +1,16 @@
 use std::{
 	borrow::Borrow,
-	cell::RefCell,
-	mem::MaybeUninit,
+	cell::{
+		BorrowMutError,
+		RefCell,
+		RefMut,
+	},
+	mem::{
+		self,
+		MaybeUninit,
+	},
+	ops::Range,
+	ptr,
 };
 
 //#[cfg(not(feature = "gl"))]
@@ -15,10 +24,20 @@ use gfx_hal::{
 		Primary,
 		Submittable,
 	},
+	format::{
+		Format,
+		ImageFeature,
+	},
 	pso::PipelineStage,
+	query::{
+		Id as QueryId,
+		Type as QueryType,
+	},
 	Device,
+	Features,
 	Graphics,
 	Instance,
+	Limits,
 	QueueFamily,
 	QueueGroup,
 	Submission,
@@ -33,10 +52,17 @@ use gfx_memory::{
 //#[cfg(not(feature = "gl"))]
 use crate::gfx_back;
 use crate::{
-	buffer::StagingBuffer,
+	buffer::{
+		BufferPool,
+		StagingBuffer,
+	},
 	gfx_back::Backend,
 	shader::*,
-	texture::TextureInfo,
+	swapchain::CompositeAlphaPreference,
+	texture::{
+		TextureInfo,
+		UnsupportedSampleCount,
+	},
 	util::TakeExt,
 	*,
 };
@@ -51,14 +77,30 @@ pub struct HALData {
 	instance: gfx_back::Instance,
 }
 
+/// Holds the queue for as long as it lives, so nothing can submit to it
+/// between presents. Created with `HALData::acquire_present_queue`.
+///
+/// Every submission goes through the same queue, so `HALData::submit`,
+/// `HALData::submit_batch` and `CommandPool::single_submit` panic while a
+/// `PresentQueue` is alive. Drop it before recording the next frame.
+pub struct PresentQueue<'a> {
+	queue_group: RefMut<'a, QueueGroup<Backend, Graphics>>,
+}
+
 impl<'a> HALData {
 	pub fn new_hal(name: &str, window: &mut Window) -> HALData {
+		Self::new_hal_from_window(name, window.window())
+	}
+
+	/// Creates the HAL for a `winit` window owned by the caller, so the
+	/// application keeps control of its own `EventsLoop`.
+	pub fn new_hal_from_window(_name: &str, window: &winit::Window) -> HALData {
 		println!("Creating new HAL");
 //		#[cfg(not(feature = "gl"))]
 		let instance = gfx_back::Instance::create("Villkiss Renderer", 1);
 
 //		#[cfg(not(feature = "gl"))]
-		let surface = instance.create_surface(window.window());
+		let surface = instance.create_surface(window);
 //		#[cfg(feature = "gl")]
 //		let surface = window.surface.take().unwrap();
 
@@ -86,6 +128,22 @@ impl<'a> HALData {
 					qf.supports_transfer()
 			})
 			.expect("Unable to open adapter");
+		unsafe { Self::from_parts(device, queue_group, surface, adapter, instance) }
+	}
+
+	/// Wraps a device opened outside of Villkiss.
+	///
+	/// # Safety
+	/// `device` and `queue_group` must have been opened from `adapter`, the queue
+	/// family must be able to present to `surface`, and all of them must come
+	/// from `instance`.
+	pub unsafe fn from_parts(
+		device: <Backend as gfx_hal::Backend>::Device,
+		queue_group: QueueGroup<Backend, Graphics>,
+		surface: <Backend as gfx_hal::Backend>::Surface,
+		adapter: Adapter<Backend>,
+		instance: gfx_back::Instance,
+	) -> HALData {
 		let allocator = SmartAllocator::new(
 			adapter.physical_device.memory_properties(),
 			4096,
@@ -104,6 +162,36 @@ impl<'a> HALData {
 		}
 	}
 
+	/// Releases the memory allocator and hands the underlying gfx-hal objects
+	/// back to the caller. Every resource created from this `HALData` borrows
+	/// it, so all of them have been dropped by the time this can be called.
+	pub fn into_parts(
+		mut self,
+	) -> (
+		<Backend as gfx_hal::Backend>::Device,
+		QueueGroup<Backend, Graphics>,
+		<Backend as gfx_hal::Backend>::Surface,
+		Adapter<Backend>,
+		gfx_back::Instance,
+	) {
+		unsafe {
+			RefCell::into_inner(MaybeUninit::take(&mut self.allocator))
+				.dispose(self.device())
+				.unwrap();
+			let parts = (
+				ptr::read(&self.device),
+				ptr::read(&self.queue_group).into_inner(),
+				ptr::read(&self.surface).into_inner(),
+				ptr::read(&self.adapter),
+				ptr::read(&self.instance),
+			);
+			// The allocator is already disposed and every other field has been
+			// moved out, so `Drop` must not run.
+			mem::forget(self);
+			parts
+		}
+	}
+
 	pub fn create_shader<
 		'b,
 		Vertex: VertexInfo,
@@ -113,20 +201,52 @@ impl<'a> HALData {
 	>(
 		&'a self,
 		shaders: ShaderModData<'b>,
-	) -> Shader<'a, Vertex, Uniforms, Index, Constants> {
+	) -> Result<Shader<'a, Vertex, Uniforms, Index, Constants>, ShaderErrors> {
 		Shader::create(self, shaders)
 	}
 
 	pub fn create_command_pool(&self) -> CommandPool { CommandPool::create(self) }
 
+	/// `staging_size` is the size in bytes of the pool's staging buffer, see
+	/// `DEFAULT_STAGING_SIZE`.
+	pub fn create_buffer_pool(
+		&'a self,
+		command_pool: &'a CommandPool<'a>,
+		staging_size: u64,
+	) -> BufferPool<'a> {
+		BufferPool::create(self, command_pool, staging_size)
+	}
+
 	pub fn create_swapchain<'b>(&'a self, staging_buf: &'b StagingBuffer) -> Swapchain<'a> {
-		Swapchain::create(self, staging_buf)
+		Swapchain::create(self, staging_buf, CompositeAlphaPreference::default())
+	}
+
+	pub fn create_swapchain_with_alpha<'b>(
+		&'a self,
+		staging_buf: &'b StagingBuffer,
+		composite_alpha: CompositeAlphaPreference,
+	) -> Swapchain<'a> {
+		Swapchain::create(self, staging_buf, composite_alpha)
 	}
 
 	pub fn create_fence(&self) -> Fence { Fence::create(self) }
 
+	pub fn create_fence_pool(&self, capacity: usize) -> FencePool {
+		FencePool::create(self, capacity)
+	}
+
+	pub fn create_event(&self) -> Event { Event::create(self) }
+
+	pub fn create_query_pool(&self, ty: QueryType, count: QueryId) -> QueryPool {
+		QueryPool::create(self, ty, count)
+	}
+
 	pub fn create_semaphore(&self) -> Semaphore { Semaphore::create(self) }
 
+	pub fn create_semaphore_pool(&self, n: usize) -> SemaphorePool {
+		SemaphorePool::create(self, n)
+	}
+
 	pub(crate) fn submit<'b, T, Ic, S, Iw, Is>(&self, sub: Submission<Ic, Iw, Is>, fence: &Fence)
 	where
 		T: 'b + Submittable<Backend, Graphics, Primary>,
@@ -140,16 +260,66 @@ impl<'a> HALData {
 		}
 	}
 
+	/// Submits `submissions` in order under a single queue borrow, with
+	/// `fence` signalled by the last one. gfx-hal has no batched submit, so
+	/// this still makes one driver call per submission.
+	pub fn submit_batch<'b, T, Ic, S, Iw, Is>(
+		&self,
+		submissions: impl IntoIterator<Item = Submission<Ic, Iw, Is>>,
+		fence: &Fence,
+	) where
+		T: 'b + Submittable<Backend, Graphics, Primary>,
+		Ic: IntoIterator<Item = &'b T>,
+		S: 'b + Borrow<<Backend as gfx_hal::Backend>::Semaphore>,
+		Iw: IntoIterator<Item = (&'b S, PipelineStage)>,
+		Is: IntoIterator<Item = &'b S>,
+	{
+		let queue = &mut self.queue_group().borrow_mut().queues[0];
+		let mut submissions = submissions.into_iter().peekable();
+		while let Some(sub) = submissions.next() {
+			let fence = if submissions.peek().is_none() {
+				Some(fence.fence())
+			} else {
+				None
+			};
+			unsafe { queue.submit(sub, fence) }
+		}
+	}
+
 	pub fn present(
 		&self,
 		swap: &Swapchain,
 		frame_idx: u32,
 		present_sems: &[&Semaphore],
 	) -> Result<(), ()> {
-		let queue = &mut self.queue_group().borrow_mut().queues[0];
-		let swap = unsafe { swap.swapchain.get_ref() }.borrow();
-		let present_sems = present_sems.iter().map(|s| s.semaphore());
-		unsafe { swap.present(queue, frame_idx, present_sems) }
+		self.acquire_present_queue()
+			.expect("The queue is held by a PresentQueue")
+			.present(swap, frame_idx, present_sems)
+	}
+
+	/// Borrows the queue until the returned handle is dropped. Fails if the
+	/// queue is already borrowed, e.g. by another `PresentQueue`.
+	pub fn acquire_present_queue(&self) -> Result<PresentQueue, BorrowMutError> {
+		Ok(PresentQueue {
+			queue_group: self.queue_group().try_borrow_mut()?,
+		})
+	}
+
+	/// Presents and then blocks until the queue is idle, so at most one frame
+	/// is ever in flight. This paces the CPU to the display without relying on
+	/// `PresentMode::Fifo`, but the CPU can no longer record the next frame
+	/// while the GPU works on this one. Semaphores and per-frame fences keep
+	/// both busy and should be preferred unless latency matters most.
+	pub fn present_and_wait_for_vsync(
+		&self,
+		swap: &Swapchain,
+		frame_idx: u32,
+		present_sems: &[&Semaphore],
+	) -> Result<(), ()> {
+		self.present(swap, frame_idx, present_sems)?;
+		self.queue_group().borrow_mut().queues[0]
+			.wait_idle()
+			.map_err(|_| ())
 	}
 
 	pub fn wait_idle(&self) {
@@ -165,6 +335,66 @@ impl<'a> HALData {
 		Texture::create(self, info, staging_buf)
 	}
 
+	/// A sampled color texture with undefined contents, to be filled with
+	/// `Texture::upload_tiled`. `info.pixels` must be `None`.
+	pub fn create_empty_texture<'b>(
+		&self,
+		info: TextureInfo<'b>,
+		staging_buf: &'b StagingBuffer,
+	) -> Texture {
+		Texture::create_empty(self, info, staging_buf)
+	}
+
+	pub fn try_create_texture<'b>(
+		&self,
+		info: TextureInfo<'b>,
+		staging_buf: &'b StagingBuffer,
+	) -> Result<Texture, UnsupportedSampleCount> {
+		Texture::try_create(self, info, staging_buf)
+	}
+
+	pub fn limits(&self) -> Limits { self.adapter.physical_device.limits() }
+
+	pub fn features(&self) -> Features { self.adapter.physical_device.features() }
+
+	/// The first depth stencil format that can be used as an optimally tiled
+	/// depth attachment. Depth textures are always transitioned with both
+	/// aspects, so formats without stencil are not considered.
+	pub fn depth_format(&self) -> Format {
+		[
+			Format::D32FloatS8Uint,
+			Format::D24UnormS8Uint,
+			Format::D16UnormS8Uint,
+		]
+		.iter()
+		.cloned()
+		.find(|format| {
+			self.adapter
+				.physical_device
+				.format_properties(Some(*format))
+				.optimal_tiling
+				.contains(ImageFeature::DEPTH_STENCIL_ATTACHMENT)
+		})
+		.expect("The device supports none of the depth attachment formats")
+	}
+
+	/// Line widths the device can rasterize. Widths in between are rounded to
+	/// a multiple of `line_width_granularity`, often `0.5`.
+	pub fn line_width_range(&self) -> Range<f32> {
+		let range = self.limits().line_width_range;
+		range[0]..range[1]
+	}
+
+	pub fn line_width_granularity(&self) -> f32 { self.limits().line_width_granularity }
+
+	pub fn adapter_name(&self) -> &str { &self.adapter.info.name }
+
+	pub fn adapter_type(&self) -> DeviceType { self.adapter.info.device_type.clone() }
+
+	/// The PCI vendor id, e.g. `0x10DE` for NVIDIA, `0x1002` for AMD and
+	/// `0x8086` for Intel.
+	pub fn adapter_vendor(&self) -> u32 { self.adapter.info.vendor as u32 }
+
 	pub(crate) fn allocator(&self) -> &RefCell<SmartAllocator<Backend>> {
 		unsafe { self.allocator.get_ref() }
 	}
@@ -193,3 +423,19 @@ impl Drop for HALData {
 		}
 	}
 }
+
+impl PresentQueue<'_> {
+	pub fn present(
+		&mut self,
+		swap: &Swapchain,
+		frame_idx: u32,
+		present_sems: &[&Semaphore],
+	) -> Result<(), ()> {
+		let queue = &mut self.queue_group.queues[0];
+		let swap = unsafe { swap.swapchain.get_ref() }.borrow();
+		let present_sems = present_sems.iter().map(|s| s.semaphore());
+		unsafe { swap.present(queue, frame_idx, present_sems) }
+	}
+
+	pub fn wait_idle(&self) { self.queue_group.queues[0].wait_idle().unwrap() }
+}
@@ -1,4 +1,8 @@
-use std::mem::MaybeUninit;
+use std::{
+	cell::Cell,
+	mem::MaybeUninit,
+	ops::Range,
+};
 
 use gfx_hal::{
 	format::{
@@ -7,9 +11,11 @@ use gfx_hal::{
 		Swizzle,
 	},
 	image::{
+		Layout,
 		SubresourceRange,
 		ViewKind,
 	},
+	pso::Descriptor,
 	Device,
 };
 
@@ -22,6 +28,10 @@ use crate::{
 pub struct ImageView<'a> {
 	data: &'a HALData,
 	view: MaybeUninit<<Backend as gfx_hal::Backend>::ImageView>,
+	range: SubresourceRange,
+	/// Live view count of the texture this view was created from, see
+	/// `Texture::view_count`.
+	live_views: Option<&'a Cell<usize>>,
 }
 
 impl<'a> ImageView<'a> {
@@ -32,25 +42,133 @@ impl<'a> ImageView<'a> {
 		kind: ViewKind,
 		aspects: Aspects,
 		mip_levels: u8,
+	) -> ImageView<'a> {
+		Self::create_range(
+			data,
+			image,
+			format,
+			kind,
+			SubresourceRange {
+				aspects,
+				levels: 0..mip_levels,
+				layers: 0..1,
+			},
+		)
+	}
+
+	pub(crate) fn create_range<'b>(
+		data: &'a HALData,
+		image: &'b <Backend as gfx_hal::Backend>::Image,
+		format: Format,
+		kind: ViewKind,
+		sub_range: SubresourceRange,
 	) -> ImageView<'a> {
 		println!("Creating ImageView");
 		let device = data.device();
-		let sub_range = SubresourceRange {
-			aspects,
-			levels: 0..mip_levels,
-			layers: 0..1,
-		};
 		let view = unsafe {
 			device
-				.create_image_view(image, kind, format, Swizzle::NO, sub_range)
+				.create_image_view(image, kind, format, Swizzle::NO, sub_range.clone())
 				.unwrap()
 		};
 		ImageView {
 			data,
 			view: MaybeUninit::new(view),
+			range: sub_range,
+			live_views: None,
 		}
 	}
 
+	/// Counts this view in `live_views` until it is dropped.
+	pub(crate) fn counted_in(mut self, live_views: &'a Cell<usize>) -> ImageView<'a> {
+		live_views.update(|views| views + 1);
+		self.live_views = Some(live_views);
+		self
+	}
+
+	pub(crate) fn create_depth_only<'b>(
+		data: &'a HALData,
+		image: &'b <Backend as gfx_hal::Backend>::Image,
+		format: Format,
+		kind: ViewKind,
+		mip_levels: u8,
+	) -> ImageView<'a> {
+		assert!(
+			format.surface_desc().aspects.contains(Aspects::DEPTH),
+			"{:?} has no depth aspect",
+			format
+		);
+		Self::create(data, image, format, kind, Aspects::DEPTH, mip_levels)
+	}
+
+	pub(crate) fn create_stencil_only<'b>(
+		data: &'a HALData,
+		image: &'b <Backend as gfx_hal::Backend>::Image,
+		format: Format,
+		kind: ViewKind,
+		mip_levels: u8,
+	) -> ImageView<'a> {
+		assert!(
+			format.surface_desc().aspects.contains(Aspects::STENCIL),
+			"{:?} has no stencil aspect",
+			format
+		);
+		Self::create(data, image, format, kind, Aspects::STENCIL, mip_levels)
+	}
+
+	/// Creates a view of a single mip level for compute shaders writing to the
+	/// image. The image must have been created with `Usage::STORAGE`.
+	pub fn create_storage<'b>(
+		data: &'a HALData,
+		image: &'b <Backend as gfx_hal::Backend>::Image,
+		format: Format,
+		kind: ViewKind,
+		mip_level: u8,
+	) -> ImageView<'a> {
+		Self::create_range(
+			data,
+			image,
+			format,
+			kind,
+			SubresourceRange {
+				aspects: Aspects::COLOR,
+				levels: mip_level..mip_level + 1,
+				layers: 0..1,
+			},
+		)
+	}
+
+	/// Views `image`, created with `original_format`, as `view_format`. The
+	/// image must have been created with `ViewCapabilities::MUTABLE_FORMAT`.
+	/// Both formats must share a surface type, so only the interpretation of
+	/// the channels changes, e.g. `Rgba8Unorm` read as `Rgba8Uint`.
+	pub fn create_reinterpreted<'b>(
+		data: &'a HALData,
+		image: &'b <Backend as gfx_hal::Backend>::Image,
+		original_format: Format,
+		view_format: Format,
+		kind: ViewKind,
+		aspects: Aspects,
+		mip_levels: u8,
+	) -> ImageView<'a> {
+		let (original_surface, _) = original_format.base_format();
+		let (view_surface, _) = view_format.base_format();
+		assert!(
+			original_surface == view_surface && aspects == Aspects::COLOR,
+			"{:?} can not be reinterpreted as {:?}",
+			original_format,
+			view_format
+		);
+		Self::create(data, image, view_format, kind, aspects, mip_levels)
+	}
+
+	pub fn storage_descriptor(&self) -> Descriptor<Backend> {
+		Descriptor::Image(self.view(), Layout::General)
+	}
+
+	pub fn mip_range(&self) -> Range<u8> { self.range.levels.clone() }
+
+	pub fn layer_range(&self) -> Range<u16> { self.range.layers.clone() }
+
 	pub(crate) fn view(&self) -> &<Backend as gfx_hal::Backend>::ImageView {
 		unsafe { self.view.get_ref() }
 	}
@@ -62,6 +180,9 @@ impl<'a> Drop for ImageView<'a> {
 		unsafe {
 			device.destroy_image_view(MaybeUninit::take(&mut self.view));
 		}
+		if let Some(live_views) = self.live_views {
+			live_views.update(|views| views - 1);
+		}
 		println!("Dropped ImageView");
 	}
 }
@@ -19,20 +19,41 @@ pub use gfx_hal;
 pub use winit;
 
 pub use crate::{
-	buffer::Buffer,
+	buffer::{
+		Buffer,
+		BufferPool,
+	},
+	bundle::ResourceBundle,
 	commandpool::CommandPool,
+	culling::{
+		BoundingBox,
+		Frustum,
+	},
 	descriptorpool::DescriptorPool,
-	fence::Fence,
+	event::Event,
+	fence::{
+		Fence,
+		FencePool,
+	},
 	framebuffer::FrameBuffer,
 	hal::HALData,
 	imageview::ImageView,
 	pipeline::{
 		BoundPipe,
 		Pipeline,
+		PipelineConfig,
+	},
+	query::QueryPool,
+	readback::PixelReadback,
+	renderpass::{
+		RenderPass,
+		RenderPassConfig,
 	},
-	renderpass::RenderPass,
 	sampler::Sampler,
-	semaphore::Semaphore,
+	semaphore::{
+		Semaphore,
+		SemaphorePool,
+	},
 	shader::{
 		Shader,
 		ShaderModData,
@@ -44,17 +65,23 @@ pub use crate::{
 mod util;
 
 pub mod buffer;
+pub mod bundle;
 pub mod commandpool;
+pub mod culling;
 pub mod descriptorpool;
+pub mod event;
 pub mod fence;
 pub mod framebuffer;
 pub mod hal;
 pub mod imageview;
 pub mod pipeline;
+pub mod query;
+pub mod readback;
 pub mod renderpass;
 pub mod sampler;
 pub mod semaphore;
 pub mod shader;
 pub mod swapchain;
+pub mod tangent;
 pub mod texture;
 pub mod window;
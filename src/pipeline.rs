@@ -11,26 +11,44 @@ use std::{
 };
 
 use gfx_hal::{
+	adapter::PhysicalDevice,
 	buffer::IndexBufferView,
 	command::RenderSubpassCommon,
+	format::{
+		Format,
+		ImageFeature,
+	},
 	pso::{
 		BlendState,
 		ColorBlendDesc,
 		ColorMask,
 		Comparison,
+		DepthBias,
 		DepthStencilDesc,
 		DepthTest,
 		Face,
 		FrontFace,
 		GraphicsPipelineDesc,
+		Multisampling,
 		PolygonMode,
+		PrimitiveRestart,
 		Rasterizer,
 		Specialization as HAL_Specialization,
 		SpecializationConstant,
+		State,
 		StencilTest,
+		VertexBufferDesc,
+	},
+	query::{
+		ControlFlags,
+		Id as QueryId,
+		PipelineStatistic,
+		Type as QueryType,
 	},
 	Device,
+	Features,
 	IndexCount,
+	IndexType as HALIndexType,
 	InstanceCount,
 	Primitive,
 };
@@ -39,9 +57,12 @@ use crate::{
 	buffer::{
 		Buffer,
 		BufferView,
+		IndexBuffer,
 	},
+	descriptorpool::dynamic_offset_count,
 	gfx_back::Backend,
 	shader::{
+		make_attributes,
 		IndexType,
 		PushConstantInfo,
 		Shader,
@@ -50,6 +71,7 @@ use crate::{
 		VertexInfo,
 	},
 	util::TakeExt,
+	QueryPool,
 	RenderPass,
 };
 
@@ -68,6 +90,10 @@ pub struct Pipeline<
 	pass: &'a RenderPass<'a>,
 	shader: &'a Shader<'a, Vertex, Uniforms, Index, Constants>,
 	pipe: MaybeUninit<<Backend as gfx_hal::Backend>::GraphicsPipeline>,
+	config: PipelineConfig,
+	specialization: PipeToHal,
+	generation: u64,
+	statistics: PipelineStatistic,
 }
 
 pub struct BoundPipe<
@@ -97,6 +123,117 @@ pub struct Specialization {
 
 pub type PipeSpecialization<'a> = ShaderSet<&'a [Specialization]>;
 
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PipelineStats {
+	pub triangles: Option<u64>,
+	pub vertex_invocations: Option<u64>,
+	pub fragment_invocations: Option<u64>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LineWidth {
+	Fixed(f32),
+	Dynamic,
+}
+
+#[derive(Debug, Clone)]
+pub struct PipelineConfig {
+	pub wireframe: bool,
+	pub line_width: LineWidth,
+	pub patch_control_points: Option<u32>,
+	pub conservative_rasterization: bool,
+	pub instance_layout: Option<InstanceLayout>,
+	/// Enables the depth bounds test. The range is set when the pipeline is
+	/// bound and can be changed per draw with `BoundPipe::set_depth_bounds`.
+	pub depth_bounds: Option<Range<f32>>,
+	/// One entry per color attachment of the subpass, in attachment order.
+	pub blend_targets: Vec<(ColorMask, BlendState)>,
+	/// Blend constants for the `ConstantColor` and `ConstantAlpha` factors.
+	/// When `None` they are dynamic and set with
+	/// `BoundPipe::set_blend_constants`.
+	pub blend_constant: Option<[f32; 4]>,
+	/// Ignored when `patch_control_points` is set.
+	pub topology: Primitive,
+	/// Restarts strips at the maximum index value. Only valid for strip
+	/// topologies.
+	pub primitive_restart: bool,
+	/// Clamps fragment depth to the depth range instead of clipping geometry
+	/// at the near and far planes, as needed for shadow volumes.
+	pub depth_clamp: bool,
+	/// Static depth bias, e.g. to fight shadow acne.
+	pub depth_bias: Option<DepthBias>,
+	/// Sets the depth bias per draw with `BoundPipe::set_depth_bias` instead,
+	/// e.g. for per-cascade shadow bias. Excludes `depth_bias`.
+	pub dynamic_depth_bias: bool,
+	/// The vertex shader writes `gl_PointSize`. Needs a `PointList` topology.
+	pub programmable_point_size: bool,
+	/// Index of the render pass subpass the pipeline is used in. Subpass 1
+	/// reads the pass' input attachments.
+	pub subpass: usize,
+	/// Format the fragment shader writes its color output as. When set it
+	/// has to match the render pass color attachment.
+	pub color_format: Option<Format>,
+	/// Depth format the shader was written against, e.g. for a matching depth
+	/// copy. When set it has to match the render pass depth attachment.
+	pub depth_format: Option<Format>,
+}
+
+/// Per-instance vertex data, bound at slot 1 after the shader's own vertices.
+#[derive(Debug, Copy, Clone)]
+pub struct InstanceLayout {
+	pub stride: u32,
+	pub attributes: &'static [Format],
+}
+
+impl InstanceLayout {
+	pub fn of<Instance: VertexInfo>() -> InstanceLayout {
+		InstanceLayout {
+			stride: Instance::STRIDE,
+			attributes: Instance::ATTRIBUTES,
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PipelineError {
+	UnsupportedFeature(Features),
+	/// The render pass depth format can't be used as a depth attachment on
+	/// this device.
+	UnsupportedDepthFormat(Format),
+	ColorFormatMismatch {
+		shader: Format,
+		pass: Format,
+	},
+	DepthFormatMismatch {
+		shader: Format,
+		pass: Format,
+	},
+}
+
+impl Default for PipelineConfig {
+	fn default() -> Self {
+		PipelineConfig {
+			wireframe: false,
+			line_width: LineWidth::Fixed(1.0),
+			patch_control_points: None,
+			conservative_rasterization: false,
+			instance_layout: None,
+			depth_bounds: None,
+			blend_targets: vec![(ColorMask::ALL, BlendState::ALPHA)],
+			blend_constant: None,
+			topology: Primitive::TriangleList,
+			primitive_restart: false,
+			depth_clamp: false,
+			depth_bias: None,
+			dynamic_depth_bias: false,
+			programmable_point_size: false,
+			subpass: 0,
+			color_format: None,
+			depth_format: None,
+		}
+	}
+}
+
 struct PipeToHal {
 	data: Vec<u8>,
 	vert_constants: Vec<SpecializationConstant>,
@@ -118,62 +255,295 @@ impl<
 		pass: &'a RenderPass<'a>,
 		shader: &'a Shader<'a, Vertex, Uniforms, Index, Constants>,
 		specialization: PipeSpecialization<'b>,
-	) -> Pipeline<'a, Vertex, Uniforms, Index, Constants> {
-		pub const RASTERIZER: Rasterizer = Rasterizer {
-			polygon_mode: PolygonMode::Fill,
+		config: PipelineConfig,
+	) -> Result<Pipeline<'a, Vertex, Uniforms, Index, Constants>, PipelineError> {
+		let features = pass.data.features();
+		if config.conservative_rasterization &&
+			!features.contains(Features::CONSERVATIVE_RASTERIZATION)
+		{
+			return Err(PipelineError::UnsupportedFeature(
+				Features::CONSERVATIVE_RASTERIZATION,
+			));
+		}
+		if config.depth_bounds.is_some() && !features.contains(Features::DEPTH_BOUNDS) {
+			return Err(PipelineError::UnsupportedFeature(Features::DEPTH_BOUNDS));
+		}
+		if config.depth_clamp && !features.contains(Features::DEPTH_CLAMP) {
+			return Err(PipelineError::UnsupportedFeature(Features::DEPTH_CLAMP));
+		}
+		if config.programmable_point_size {
+			assert_eq!(
+				config.topology,
+				Primitive::PointList,
+				"Programmable point sizes need a PointList topology"
+			);
+			if !features.contains(Features::POINT_SIZE) {
+				return Err(PipelineError::UnsupportedFeature(Features::POINT_SIZE));
+			}
+			if shader.has_tessellation() &&
+				!features.contains(Features::SHADER_TESSELLATION_AND_GEOMETRY_POINT_SIZE)
+			{
+				return Err(PipelineError::UnsupportedFeature(
+					Features::SHADER_TESSELLATION_AND_GEOMETRY_POINT_SIZE,
+				));
+			}
+		}
+		assert!(
+			!(config.dynamic_depth_bias && config.depth_bias.is_some()),
+			"A pipeline can't have both a static and a dynamic depth bias"
+		);
+		#[cfg(debug_assertions)]
+		{
+			if let Some(bias) = config.depth_bias {
+				if !config.depth_clamp && bias.clamp == 0.0 && bias.slope_factor != 0.0 {
+					println!(
+						"Warning: Unclamped depth bias with slope factor {} can push steep polygons outside of [0, 1]",
+						bias.slope_factor
+					);
+				}
+			}
+		}
+		let depth_format = pass.depth_format();
+		let depth_features = pass
+			.data
+			.adapter()
+			.physical_device
+			.format_properties(Some(depth_format))
+			.optimal_tiling;
+		if !depth_features.contains(ImageFeature::DEPTH_STENCIL_ATTACHMENT) {
+			return Err(PipelineError::UnsupportedDepthFormat(depth_format));
+		}
+		if let Some(format) = config.depth_format {
+			if format != depth_format {
+				return Err(PipelineError::DepthFormatMismatch {
+					shader: format,
+					pass: depth_format,
+				});
+			}
+		}
+		if let Some(format) = config.color_format {
+			if format != pass.color_format() {
+				return Err(PipelineError::ColorFormatMismatch {
+					shader: format,
+					pass: pass.color_format(),
+				});
+			}
+		}
+		assert!(
+			config.subpass < pass.subpass_count(),
+			"The render pass has no subpass {}",
+			config.subpass
+		);
+		if config.primitive_restart {
+			assert!(
+				config.patch_control_points.is_none() &&
+					(config.topology == Primitive::LineStrip ||
+						config.topology == Primitive::TriangleStrip),
+				"Primitive restart is only supported for strip topologies, not {:?}",
+				config.topology
+			);
+		}
+		assert_eq!(
+			config.patch_control_points.is_some(),
+			shader.has_tessellation(),
+			"patch_control_points must be set exactly when the shader has tessellation stages"
+		);
+		if let Some(points) = config.patch_control_points {
+			assert!(
+				points > 0 && points <= pass.data.limits().max_patch_size as u32,
+				"Unsupported patch control point count: {}",
+				points
+			);
+		}
+
+		println!("Creating Pipeline");
+		let pipe_to_hal = PipeToHal::create(specialization);
+		let pipe = Self::build_pipe(pass, shader, &pipe_to_hal, &config);
+
+		Ok(Pipeline {
+			pass,
+			shader,
+			pipe: MaybeUninit::new(pipe),
+			config,
+			specialization: pipe_to_hal,
+			generation: shader.generation(),
+			statistics: PipelineStatistic::empty(),
+		})
+	}
+
+	pub fn create_with_statistics<'b>(
+		pass: &'a RenderPass<'a>,
+		shader: &'a Shader<'a, Vertex, Uniforms, Index, Constants>,
+		specialization: PipeSpecialization<'b>,
+		config: PipelineConfig,
+		queries: &[PipelineStatistic],
+	) -> Result<Pipeline<'a, Vertex, Uniforms, Index, Constants>, PipelineError> {
+		if !pass
+			.data
+			.features()
+			.contains(Features::PIPELINE_STATISTICS_QUERY)
+		{
+			return Err(PipelineError::UnsupportedFeature(
+				Features::PIPELINE_STATISTICS_QUERY,
+			));
+		}
+		let mut pipeline = Self::create(pass, shader, specialization, config)?;
+		pipeline.statistics = queries
+			.iter()
+			.fold(PipelineStatistic::empty(), |acc, stat| acc | *stat);
+		Ok(pipeline)
+	}
+
+	/// Creates a query pool recording the statistics this pipeline was created
+	/// with. Record into it with `BoundPipe::begin_query`.
+	pub fn create_statistics_pool(&self, count: QueryId) -> QueryPool<'a> {
+		self.pass
+			.data
+			.create_query_pool(QueryType::PipelineStatistics(self.statistics), count)
+	}
+
+	pub fn read_statistics(&self, query_pool: &QueryPool, idx: QueryId) -> PipelineStats {
+		let enabled = self.statistics.bits().count_ones() as usize;
+		let values = query_pool.read_results(idx..idx + 1, enabled);
+		let mut values = values.into_iter();
+		let mut stats = PipelineStats::default();
+		// Results are written in the order of the statistic bits.
+		for bit in 0..32 {
+			let stat = match PipelineStatistic::from_bits(1 << bit) {
+				Some(stat) if self.statistics.contains(stat) => stat,
+				_ => continue,
+			};
+			let value = values.next();
+			if stat == PipelineStatistic::INPUT_ASSEMBLY_PRIMITIVES {
+				stats.triangles = value;
+			} else if stat == PipelineStatistic::VERTEX_SHADER_INVOCATIONS {
+				stats.vertex_invocations = value;
+			} else if stat == PipelineStatistic::FRAGMENT_SHADER_INVOCATIONS {
+				stats.fragment_invocations = value;
+			}
+		}
+		stats
+	}
+
+	fn build_pipe(
+		pass: &'a RenderPass<'a>,
+		shader: &'a Shader<'a, Vertex, Uniforms, Index, Constants>,
+		pipe_to_hal: &PipeToHal,
+		config: &PipelineConfig,
+	) -> <Backend as gfx_hal::Backend>::GraphicsPipeline {
+		let line_width = match config.line_width {
+			LineWidth::Fixed(width) => State::Static(width),
+			LineWidth::Dynamic => State::Dynamic,
+		};
+		let rasterizer = Rasterizer {
+			polygon_mode: if config.wireframe {
+				PolygonMode::Line(line_width)
+			} else {
+				PolygonMode::Fill
+			},
 			cull_face: Face::BACK,
 			front_face: FrontFace::CounterClockwise,
-			depth_clamping: false,
-			depth_bias: None,
-			conservative: false,
+			depth_clamping: config.depth_clamp,
+			depth_bias: if config.dynamic_depth_bias {
+				Some(State::Dynamic)
+			} else {
+				config.depth_bias.map(State::Static)
+			},
+			conservative: config.conservative_rasterization,
+		};
+		let primitive = match config.patch_control_points {
+			Some(points) => Primitive::PatchList(points as u8),
+			None => config.topology,
 		};
 
-		println!("Creating Pipeline");
-		let device = pass.swapchain.data.device();
-		let pipe_to_hal = PipeToHal::create(specialization);
+		let device = pass.data.device();
 		let shad_set = shader.make_set(pipe_to_hal.make_hal());
 		let pipe_layout = shader.pipe_layout();
 		let desc_layout = shader.desc_layout();
-		let subpass = pass.make_subpass();
-
-		let mut pipeline_desc = GraphicsPipelineDesc::new(
-			shad_set,
-			Primitive::TriangleList,
-			RASTERIZER,
-			pipe_layout,
-			subpass,
-		);
+		let subpass = pass.make_subpass(config.subpass);
+
+		let mut pipeline_desc =
+			GraphicsPipelineDesc::new(shad_set, primitive, rasterizer, pipe_layout, subpass);
+		if config.patch_control_points.is_some() {
+			pipeline_desc.input_assembler.primitive_restart = PrimitiveRestart::Disabled;
+		} else if config.primitive_restart {
+			pipeline_desc.input_assembler.primitive_restart = match Index::HAL {
+				HALIndexType::U16 => PrimitiveRestart::U16,
+				HALIndexType::U32 => PrimitiveRestart::U32,
+			};
+		}
+		let samples = pass.config().samples;
+		if samples > 1 {
+			pipeline_desc.multisampling = Some(Multisampling {
+				rasterization_samples: samples,
+				sample_shading: None,
+				sample_mask: !0,
+				alpha_coverage: false,
+				alpha_to_one: false,
+			});
+		}
 		pipeline_desc.depth_stencil = DepthStencilDesc {
 			depth: DepthTest::On {
 				fun: Comparison::Less,
 				write: true,
 			},
-			depth_bounds: false,
+			depth_bounds: config.depth_bounds.is_some(),
 			stencil: StencilTest::Off,
 		};
-		pipeline_desc
-			.blender
-			.targets
-			.push(ColorBlendDesc(ColorMask::ALL, BlendState::ALPHA));
+		pipeline_desc.blender.targets.extend(
+			config
+				.blend_targets
+				.iter()
+				.map(|(mask, blend)| ColorBlendDesc(*mask, *blend)),
+		);
+		pipeline_desc.baked_states.blend_color = config.blend_constant;
 
 		shader.describe_vertices(
 			&mut pipeline_desc.vertex_buffers,
 			&mut pipeline_desc.attributes,
 		);
+		if let Some(instance) = config.instance_layout {
+			pipeline_desc.vertex_buffers.push(VertexBufferDesc {
+				binding: 1,
+				stride: instance.stride,
+				rate: 1,
+			});
+			let first_location = pipeline_desc.attributes.len() as u32;
+			pipeline_desc.attributes.extend(make_attributes(
+				1,
+				first_location,
+				instance.attributes,
+			));
+		}
 
-		let pipe = unsafe {
+		unsafe {
 			device
 				.create_graphics_pipeline(&pipeline_desc, None)
 				.unwrap()
-		};
+		}
+	}
 
-		Pipeline {
-			pass,
-			shader,
-			pipe: MaybeUninit::new(pipe),
+	pub fn is_stale(&self) -> bool { self.generation != self.shader.generation() }
+
+	/// Recreates the pipeline if its shader has been reloaded since it was
+	/// built. Returns whether a rebuild happened.
+	pub fn refresh(&mut self) -> bool {
+		if !self.is_stale() {
+			return false;
+		}
+		println!("Rebuilding Pipeline");
+		let pipe = Self::build_pipe(self.pass, self.shader, &self.specialization, &self.config);
+		let device = self.pass.data.device();
+		unsafe {
+			device.destroy_graphics_pipeline(MaybeUninit::take(&mut self.pipe));
 		}
+		self.pipe = MaybeUninit::new(pipe);
+		self.generation = self.shader.generation();
+		true
 	}
 
+	pub fn config(&self) -> &PipelineConfig { &self.config }
+
 	pub fn bind_pipe<
 		C: BorrowMut<<Backend as gfx_hal::Backend>::CommandBuffer>,
 		F: FnOnce(&mut BoundPipe<C, Vertex, Uniforms, Index, Constants>),
@@ -184,6 +554,9 @@ impl<
 	) {
 		unsafe {
 			encoder.bind_graphics_pipeline(self.pipe.get_ref());
+			if let Some(range) = &self.config.depth_bounds {
+				encoder.set_depth_bounds(range.clone());
+			}
 		}
 		let mut bp = BoundPipe {
 			pipeline: self,
@@ -210,6 +583,19 @@ impl<
 		}
 	}
 
+	pub fn bind_instance_buffer<'b, T: Buffer<'b>>(&mut self, buffer: &BufferView<'b, T>) {
+		let instance = self
+			.pipeline
+			.config
+			.instance_layout
+			.expect("Pipeline was not created with an instance layout");
+		assert_eq!(buffer.desc().type_size(), instance.stride as _);
+		unsafe {
+			self.encoder
+				.bind_vertex_buffers(1, once((buffer.hal_buffer(), buffer.offset())));
+		}
+	}
+
 	pub fn bind_index_buffer<'b, T: Buffer<'b>>(&mut self, buffer: &BufferView<'b, T>) {
 		assert_eq!(buffer.type_id(), TypeId::of::<Index>());
 		unsafe {
@@ -221,13 +607,31 @@ impl<
 		}
 	}
 
+	pub fn bind_indices(&mut self, indices: &IndexBuffer<Index>) {
+		self.bind_index_buffer(indices.view())
+	}
+
 	pub fn bind_descriptors(&mut self, descriptors: &<Backend as gfx_hal::Backend>::DescriptorSet) {
+		self.bind_descriptors_with_offsets(descriptors, &[])
+	}
+
+	pub fn bind_descriptors_with_offsets(
+		&mut self,
+		descriptors: &<Backend as gfx_hal::Backend>::DescriptorSet,
+		dynamic_offsets: &[u32],
+	) {
+		let dynamic_count = dynamic_offset_count(self.pipeline.shader.bindings());
+		assert_eq!(
+			dynamic_count,
+			dynamic_offsets.len(),
+			"Every element of a dynamic descriptor binding needs exactly one offset"
+		);
 		unsafe {
 			self.encoder.bind_graphics_descriptor_sets(
 				self.pipeline.shader.pipe_layout(),
 				0,
 				once(descriptors),
-				&[],
+				dynamic_offsets,
 			);
 		}
 	}
@@ -235,6 +639,100 @@ impl<
 	pub fn draw_indexed(&mut self, indices: Range<IndexCount>, instances: Range<InstanceCount>) {
 		unsafe { self.encoder.draw_indexed(indices, 0, instances) }
 	}
+
+	/// Draws inside an occlusion query. Read the passed sample count with
+	/// `QueryPool::read_occlusion` once the frame has finished.
+	pub fn draw_with_occlusion_query(
+		&mut self,
+		query_pool: &QueryPool,
+		idx: QueryId,
+		indices: Range<IndexCount>,
+		instances: Range<InstanceCount>,
+	) {
+		assert_eq!(
+			query_pool.ty(),
+			QueryType::Occlusion,
+			"Query pool is not an occlusion pool"
+		);
+		self.begin_query(query_pool, idx);
+		self.draw_indexed(indices, instances);
+		self.end_query(query_pool, idx);
+	}
+
+	pub fn begin_query(&mut self, query_pool: &QueryPool, idx: QueryId) {
+		unsafe {
+			self.encoder
+				.begin_query(query_pool.query(idx), ControlFlags::empty())
+		}
+	}
+
+	pub fn end_query(&mut self, query_pool: &QueryPool, idx: QueryId) {
+		unsafe { self.encoder.end_query(query_pool.query(idx)) }
+	}
+
+	pub fn set_depth_bounds(&mut self, range: Range<f32>) {
+		assert!(
+			self.pipeline.config.depth_bounds.is_some(),
+			"Pipeline was not created with a depth bounds test"
+		);
+		unsafe { self.encoder.set_depth_bounds(range) }
+	}
+
+	pub fn set_depth_bias(&mut self, constant_factor: f32, clamp: f32, slope_factor: f32) {
+		assert!(
+			self.pipeline.config.dynamic_depth_bias,
+			"Pipeline was not created with a dynamic depth bias"
+		);
+		unsafe {
+			self.encoder.set_depth_bias(DepthBias {
+				const_factor: constant_factor,
+				clamp,
+				slope_factor,
+			})
+		}
+	}
+
+	pub fn set_blend_constants(&mut self, constants: [f32; 4]) {
+		assert!(
+			self.pipeline.config.blend_constant.is_none(),
+			"Pipeline was created with static blend constants"
+		);
+		unsafe { self.encoder.set_blend_constants(constants) }
+	}
+
+	pub fn set_line_width(&mut self, width: f32) {
+		assert_eq!(
+			self.pipeline.config.line_width,
+			LineWidth::Dynamic,
+			"Pipeline was not created with a dynamic line width"
+		);
+		#[cfg(debug_assertions)]
+		{
+			let data = self.pipeline.pass.data;
+			if width != 1.0 && !data.features().contains(Features::LINE_WIDTH) {
+				println!(
+					"Warning: Line width {} requested but wide lines are unsupported",
+					width
+				);
+			}
+			let range = data.line_width_range();
+			assert!(
+				width >= range.start && width <= range.end,
+				"Line width {} is outside of the supported range {:?}",
+				width,
+				range
+			);
+			let granularity = data.line_width_granularity();
+			let steps = (width - range.start) / granularity;
+			if granularity > 0.0 && (steps - steps.round()).abs() > 1e-3 {
+				println!(
+					"Warning: Line width {} will be rounded to a multiple of {}",
+					width, granularity
+				);
+			}
+		}
+		unsafe { self.encoder.set_line_width(width) }
+	}
 }
 
 impl<
@@ -269,7 +767,7 @@ impl<
 	> Drop for Pipeline<'a, Vertex, Uniforms, Index, Constants>
 {
 	fn drop(&mut self) {
-		let device = self.pass.swapchain.data.device();
+		let device = self.pass.data.device();
 		unsafe {
 			device.destroy_graphics_pipeline(MaybeUninit::take(&mut self.pipe));
 		}
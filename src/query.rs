@@ -0,0 +1,106 @@
+use std::{
+	mem::MaybeUninit,
+	ops::Range,
+};
+
+use byteorder::{
+	ByteOrder,
+	NativeEndian as NE,
+};
+use gfx_hal::{
+	query::{
+		Id,
+		Query,
+		ResultFlags,
+		Type as QueryType,
+	},
+	Device,
+};
+
+use crate::{
+	gfx_back::Backend,
+	util::TakeExt,
+	HALData,
+};
+
+pub struct QueryPool<'a> {
+	data: &'a HALData,
+	ty: QueryType,
+	count: Id,
+	pool: MaybeUninit<<Backend as gfx_hal::Backend>::QueryPool>,
+}
+
+impl<'a> QueryPool<'a> {
+	pub(crate) fn create(data: &'a HALData, ty: QueryType, count: Id) -> QueryPool<'a> {
+		println!("Creating QueryPool");
+		let pool = unsafe { data.device().create_query_pool(ty, count).unwrap() };
+		QueryPool {
+			data,
+			ty,
+			count,
+			pool: MaybeUninit::new(pool),
+		}
+	}
+
+	pub fn query(&self, id: Id) -> Query<Backend> {
+		assert!(id < self.count, "Query {} is out of range", id);
+		Query {
+			pool: self.pool(),
+			id,
+		}
+	}
+
+	/// Waits for the queries in `ids` and returns `values_per_query` 64 bit
+	/// results for each of them.
+	pub(crate) fn read_results(&self, ids: Range<Id>, values_per_query: usize) -> Vec<u64> {
+		assert!(
+			ids.end <= self.count,
+			"Query range {:?} is out of range",
+			ids
+		);
+		let stride = values_per_query * 8;
+		let mut bytes = vec![0u8; (ids.end - ids.start) as usize * stride];
+		unsafe {
+			self.data
+				.device()
+				.get_query_pool_results(
+					self.pool(),
+					ids,
+					&mut bytes,
+					stride as _,
+					ResultFlags::BITS_64 | ResultFlags::WAIT,
+				)
+				.unwrap();
+		}
+		bytes.chunks(8).map(NE::read_u64).collect()
+	}
+
+	/// Waits for occlusion query `idx` and returns the number of samples that
+	/// passed the depth and stencil tests.
+	pub fn read_occlusion(&self, idx: Id) -> u64 {
+		assert_eq!(
+			self.ty,
+			QueryType::Occlusion,
+			"Query pool is not an occlusion pool"
+		);
+		self.read_results(idx..idx + 1, 1)[0]
+	}
+
+	pub fn ty(&self) -> QueryType { self.ty }
+
+	pub fn count(&self) -> Id { self.count }
+
+	pub fn pool(&self) -> &<Backend as gfx_hal::Backend>::QueryPool {
+		unsafe { self.pool.get_ref() }
+	}
+}
+
+impl<'a> Drop for QueryPool<'a> {
+	fn drop(&mut self) {
+		let device = self.data.device();
+		unsafe {
+			device.destroy_query_pool(MaybeUninit::take(&mut self.pool));
+		}
+		println!("Dropped QueryPool");
+	}
+}
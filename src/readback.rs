@@ -0,0 +1,199 @@
+use std::iter::once;
+
+use gfx_hal::{
+	buffer::Usage as BufferUsage,
+	command::BufferImageCopy,
+	format::{
+		Aspects,
+		Format,
+	},
+	image::{
+		Extent,
+		Layout,
+		Offset,
+		SubresourceLayers,
+		Usage as ImageUsage,
+	},
+	memory::Properties,
+	Device,
+};
+
+use gfx_memory::Block;
+
+use crate::{
+	buffer::{
+		inner::InnerBuffer,
+		BaseBuffer,
+	},
+	renderpass::RenderPassConfig,
+	CommandPool,
+	Fence,
+	FrameBuffer,
+	HALData,
+	RenderPass,
+	Texture,
+};
+
+pub const PICKING_FORMAT: Format = Format::R32Uint;
+
+/// Renders object IDs into a `PICKING_FORMAT` texture and reads single pixels
+/// of it back. Draw into it with a pass from `create_renderpass` and a
+/// framebuffer from `create_framebuffer`.
+pub struct PixelReadback<'a> {
+	data: &'a HALData,
+	extent: Extent,
+	command_pool: CommandPool<'a>,
+	fence: Fence<'a>,
+	target: Texture<'a>,
+	depth: Texture<'a>,
+	readback: BaseBuffer<'a>,
+}
+
+impl<'a> PixelReadback<'a> {
+	pub fn create(data: &'a HALData, width: u32, height: u32) -> PixelReadback<'a> {
+		println!("Creating PixelReadback");
+		let extent = Extent {
+			width,
+			height,
+			depth: 1,
+		};
+		let command_pool = data.create_command_pool();
+		let fence = data.create_fence();
+		let target = Texture::create_render_target(
+			data,
+			extent,
+			PICKING_FORMAT,
+			ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+			1,
+			&command_pool,
+			&fence,
+		);
+		let depth = Texture::create_render_target(
+			data,
+			extent,
+			data.depth_format(),
+			ImageUsage::DEPTH_STENCIL_ATTACHMENT,
+			1,
+			&command_pool,
+			&fence,
+		);
+		let readback = BaseBuffer::create(
+			data,
+			BufferUsage::TRANSFER_DST,
+			Properties::CPU_VISIBLE,
+			Properties::COHERENT,
+			std::mem::size_of::<u32>() as _,
+		);
+
+		PixelReadback {
+			data,
+			extent,
+			command_pool,
+			fence,
+			target,
+			depth,
+			readback,
+		}
+	}
+
+	/// A pass clearing and rendering into the ID and depth textures. The ID
+	/// texture is left in `Layout::ColorAttachmentOptimal`.
+	pub fn create_renderpass(&self) -> RenderPass<'a> {
+		RenderPass::create_offscreen(
+			self.data,
+			RenderPassConfig::default(),
+			PICKING_FORMAT,
+			self.depth.format,
+		)
+	}
+
+	pub fn create_framebuffer<'p>(&self, pass: &'p RenderPass<'p>) -> FrameBuffer<'p> {
+		assert_eq!(
+			pass.color_format(),
+			PICKING_FORMAT,
+			"The render pass does not render into the picking format"
+		);
+		pass.create_framebuffer_same_size(
+			&[vec![self.target.view(), self.depth.view()]],
+			self.extent,
+		)
+	}
+
+	pub fn read_pixel(&self, x: u32, y: u32) -> u32 {
+		assert!(
+			x < self.extent.width && y < self.extent.height,
+			"Attempted to read a pixel outside of the readback target!"
+		);
+		let device = self.data.device();
+		let copy = BufferImageCopy {
+			buffer_offset: 0,
+			buffer_width: 0,
+			buffer_height: 0,
+			image_layers: SubresourceLayers {
+				aspects: Aspects::COLOR,
+				level: 0,
+				layers: 0..1,
+			},
+			image_offset: Offset {
+				x: x as i32,
+				y: y as i32,
+				z: 0,
+			},
+			image_extent: Extent {
+				width: 1,
+				height: 1,
+				depth: 1,
+			},
+		};
+		self.fence.wait_n_reset();
+		self.command_pool
+			.single_submit(&[], &[], &self.fence, |cmd_buf| {
+				let image = self.target.image();
+				Texture::transition_image_layout(
+					cmd_buf,
+					image,
+					0,
+					0..1,
+					Layout::ColorAttachmentOptimal..Layout::TransferSrcOptimal,
+				);
+				unsafe {
+					cmd_buf.copy_image_to_buffer(
+						image,
+						Layout::TransferSrcOptimal,
+						self.readback.hal_buffer(),
+						once(copy),
+					);
+				}
+				Texture::transition_image_layout(
+					cmd_buf,
+					image,
+					0,
+					0..1,
+					Layout::TransferSrcOptimal..Layout::ColorAttachmentOptimal,
+				);
+			});
+		self.fence.wait();
+
+		let block = self.readback.block();
+		let offset = block.range().start;
+		let range = offset..offset + std::mem::size_of::<u32>() as u64;
+		unsafe {
+			let (map, mapped) = self.readback.map_range(range);
+			self.readback.invalidate(mapped);
+			let value = std::ptr::read(map as *const u32);
+			device.unmap_memory(block.memory());
+			value
+		}
+	}
+
+	pub fn texture(&self) -> &Texture { &self.target }
+
+	pub fn extent(&self) -> &Extent { &self.extent }
+}
+
+impl<'a> Drop for PixelReadback<'a> {
+	fn drop(&mut self) {
+		self.fence.wait();
+		println!("Dropped PixelReadback");
+	}
+}
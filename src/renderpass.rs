@@ -30,6 +30,8 @@ use crate::{
 	pipeline::{
 		PipeSpecialization,
 		Pipeline,
+		PipelineConfig,
+		PipelineError,
 	},
 	shader::{
 		IndexType,
@@ -40,80 +42,292 @@ use crate::{
 	},
 	util::TakeExt,
 	FrameBuffer,
+	HALData,
 	ImageView,
 	Swapchain,
 };
 
 pub struct RenderPass<'a> {
-	pub(crate) swapchain: &'a Swapchain<'a>,
+	pub(crate) data: &'a HALData,
+	/// `None` for passes rendering into textures.
+	pub(crate) swapchain: Option<&'a Swapchain<'a>>,
 	pub(crate) pass: MaybeUninit<<Backend as gfx_hal::Backend>::RenderPass>,
+	config: RenderPassConfig,
+	color_format: Format,
+	depth_format: Format,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderPassConfig {
+	/// `AttachmentLoadOp::Load` keeps the previous contents of the color
+	/// attachment. The image must already hold valid data when the pass
+	/// begins: in `Layout::Present` for swapchain images, or in
+	/// `Layout::ColorAttachmentOptimal` for a multisampled color attachment.
+	pub color_load_op: AttachmentLoadOp,
+	pub inputs: Vec<(usize, Layout)>,
+	/// Sample count of the color and depth attachments. Anything above 1
+	/// needs a resolve attachment to present from.
+	pub samples: u8,
+	pub resolve: Option<(usize, usize)>,
+}
+
+impl Default for RenderPassConfig {
+	fn default() -> Self {
+		RenderPassConfig {
+			color_load_op: AttachmentLoadOp::Clear,
+			inputs: Vec::new(),
+			samples: 1,
+			resolve: None,
+		}
+	}
+}
+
+impl RenderPassConfig {
+	pub fn with_load_op_load(mut self) -> Self {
+		self.color_load_op = AttachmentLoadOp::Load;
+		self
+	}
+
+	/// Reads `attachment_idx` as an input attachment in a second subpass,
+	/// which runs after the first and renders into the same color attachment.
+	/// Pipelines for it set `PipelineConfig::subpass` to 1.
+	pub fn add_input_attachment(mut self, attachment_idx: usize, layout: Layout) -> Self {
+		assert!(
+			layout == Layout::ShaderReadOnlyOptimal || layout == Layout::General,
+			"Input attachments must be in ShaderReadOnlyOptimal or General layout, not {:?}",
+			layout
+		);
+		self.inputs.push((attachment_idx, layout));
+		self
+	}
+
+	/// Resolves the multisampled color attachment into a single sampled
+	/// swapchain image. The two indices place the multisampled color and the
+	/// resolve target among the pass' three attachments; the depth attachment
+	/// takes the remaining one.
+	pub fn set_resolve_attachment(
+		mut self,
+		multisampled_attachment: usize,
+		resolve_attachment: usize,
+	) -> Self {
+		assert!(
+			multisampled_attachment < 3 &&
+				resolve_attachment < 3 &&
+				multisampled_attachment != resolve_attachment,
+			"Resolve attachments must be two distinct indices below 3, got {} and {}",
+			multisampled_attachment,
+			resolve_attachment
+		);
+		self.resolve = Some((multisampled_attachment, resolve_attachment));
+		self
+	}
+
+	/// Indices of the color, depth and resolve attachments.
+	pub fn attachment_indices(&self) -> (usize, usize, Option<usize>) {
+		match self.resolve {
+			Some((color, resolve)) => (color, 3 - color - resolve, Some(resolve)),
+			None => (0, 1, None),
+		}
+	}
 }
 
 impl<'a> RenderPass<'a> {
-	pub(crate) fn create(swapchain: &'a Swapchain) -> RenderPass<'a> {
+	pub(crate) fn create(swapchain: &'a Swapchain, config: RenderPassConfig) -> RenderPass<'a> {
+		let (_, formats, _) = swapchain
+			.data
+			.surface()
+			.borrow()
+			.compatibility(&swapchain.data.adapter().physical_device);
+		let surface_color_format = match formats {
+			Some(choices) => choices
+				.into_iter()
+				.find(|format| format.base_format().1 == ChannelType::Srgb)
+				.unwrap(),
+			None => Format::Rgba8Srgb,
+		};
+		Self::build(
+			swapchain.data,
+			Some(swapchain),
+			config,
+			surface_color_format,
+			swapchain.depth_tex.format,
+		)
+	}
+
+	/// A pass rendering into textures instead of the swapchain. Its color
+	/// attachment is left in `Layout::ColorAttachmentOptimal`.
+	pub(crate) fn create_offscreen(
+		data: &'a HALData,
+		config: RenderPassConfig,
+		color_format: Format,
+		depth_format: Format,
+	) -> RenderPass<'a> {
+		Self::build(data, None, config, color_format, depth_format)
+	}
+
+	fn build(
+		data: &'a HALData,
+		swapchain: Option<&'a Swapchain<'a>>,
+		config: RenderPassConfig,
+		color_format: Format,
+		depth_format: Format,
+	) -> RenderPass<'a> {
 		println!("Creating Renderpass");
-		let device = swapchain.data.device();
+		let device = data.device();
 		let render_pass = {
-			let (capabilities, formats, _) = swapchain
-				.data
-				.surface()
-				.borrow()
-				.compatibility(&swapchain.data.adapter().physical_device);
-			let surface_color_format = match formats {
-				Some(choices) => choices
-					.into_iter()
-					.find(|format| format.base_format().1 == ChannelType::Srgb)
-					.unwrap(),
-				None => Format::Rgba8Srgb,
+			// Where the final color ends up: presented, or read by later passes.
+			let target_layout = if swapchain.is_some() {
+				Layout::Present
+			} else {
+				Layout::ColorAttachmentOptimal
 			};
 
-			let color_attachment = Attachment {
-				format: Some(surface_color_format),
-				samples: 1,
-				ops: AttachmentOps::new(AttachmentLoadOp::Clear, AttachmentStoreOp::Store),
-				stencil_ops: AttachmentOps::DONT_CARE,
-				layouts: Layout::Undefined..Layout::Present,
-			};
+			assert_eq!(
+				config.samples > 1,
+				config.resolve.is_some(),
+				"Multisampled render passes need exactly one resolve attachment"
+			);
+			let (color_idx, depth_idx, resolve_idx) = config.attachment_indices();
 
-			let depth_stencil = &swapchain.depth_tex;
+			let loads = config.color_load_op == AttachmentLoadOp::Load;
+			// Swapchain images come back from presentation in `Layout::Present`,
+			// while a multisampled color attachment stays in the layout the last
+			// pass left it in.
+			let initial_color = match (loads, config.resolve) {
+				(false, _) => Layout::Undefined,
+				(true, Some(_)) => Layout::ColorAttachmentOptimal,
+				(true, None) => target_layout,
+			};
+			let color_attachment = if config.resolve.is_some() {
+				Attachment {
+					format: Some(color_format),
+					samples: config.samples,
+					ops: AttachmentOps::new(config.color_load_op, AttachmentStoreOp::DontCare),
+					stencil_ops: AttachmentOps::DONT_CARE,
+					layouts: initial_color..Layout::ColorAttachmentOptimal,
+				}
+			} else {
+				Attachment {
+					format: Some(color_format),
+					samples: 1,
+					ops: AttachmentOps::new(config.color_load_op, AttachmentStoreOp::Store),
+					stencil_ops: AttachmentOps::DONT_CARE,
+					layouts: initial_color..target_layout,
+				}
+			};
 
 			let depth_attachment = Attachment {
-				format: Some(depth_stencil.format),
-				samples: 1,
+				format: Some(depth_format),
+				samples: config.samples,
 				ops: AttachmentOps::new(AttachmentLoadOp::Clear, AttachmentStoreOp::DontCare),
 				stencil_ops: AttachmentOps::DONT_CARE,
 				layouts: Layout::Undefined..Layout::DepthStencilAttachmentOptimal,
 			};
 
-			let subpass = SubpassDesc {
-				colors: &[(0, Layout::ColorAttachmentOptimal)],
-				depth_stencil: Some(&(1, Layout::DepthStencilAttachmentOptimal)),
+			let mut attachments = vec![None, None, None];
+			attachments[color_idx] = Some(color_attachment);
+			attachments[depth_idx] = Some(depth_attachment);
+			let mut resolves = Vec::new();
+			if let Some(resolve) = resolve_idx {
+				attachments[resolve] = Some(Attachment {
+					format: Some(color_format),
+					samples: 1,
+					ops: AttachmentOps::new(AttachmentLoadOp::DontCare, AttachmentStoreOp::Store),
+					stencil_ops: AttachmentOps::DONT_CARE,
+					layouts: Layout::Undefined..target_layout,
+				});
+				resolves.push((resolve, Layout::ColorAttachmentOptimal));
+			}
+			let attachments = attachments.into_iter().flatten().collect::<Vec<_>>();
+			assert!(
+				config
+					.inputs
+					.iter()
+					.all(|(idx, _)| *idx < attachments.len()),
+				"Input attachment index out of range"
+			);
+			assert!(
+				config.inputs.iter().all(|(idx, _)| *idx != color_idx),
+				"The input subpass renders into the color attachment, so it can't also read it"
+			);
+			assert!(
+				config
+					.inputs
+					.iter()
+					.all(|(idx, _)| Some(*idx) != resolve_idx),
+				"The resolve attachment can't be read as an input attachment"
+			);
+
+			let has_inputs = !config.inputs.is_empty();
+			let colors = [(color_idx, Layout::ColorAttachmentOptimal)];
+			let depth = (depth_idx, Layout::DepthStencilAttachmentOptimal);
+			let mut subpasses = vec![SubpassDesc {
+				colors: &colors,
+				depth_stencil: Some(&depth),
 				inputs: &[],
-				resolves: &[],
+				resolves: if has_inputs { &[] } else { &resolves[..] },
 				preserves: &[],
-			};
+			}];
 
-			let dependency = SubpassDependency {
+			let mut dependencies = vec![SubpassDependency {
 				passes: SubpassRef::External..SubpassRef::Pass(0),
 				stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..
 					PipelineStage::COLOR_ATTACHMENT_OUTPUT,
-				accesses: Access::empty()..
-					(Access::COLOR_ATTACHMENT_READ | Access::COLOR_ATTACHMENT_WRITE),
-			};
+				accesses: if loads {
+					Access::COLOR_ATTACHMENT_READ | Access::COLOR_ATTACHMENT_WRITE
+				} else {
+					Access::empty()
+				}..(Access::COLOR_ATTACHMENT_READ | Access::COLOR_ATTACHMENT_WRITE),
+			}];
+
+			if has_inputs {
+				subpasses.push(SubpassDesc {
+					colors: &colors,
+					depth_stencil: None,
+					inputs: &config.inputs,
+					resolves: &resolves,
+					preserves: &[],
+				});
+				dependencies.push(SubpassDependency {
+					passes: SubpassRef::Pass(0)..SubpassRef::Pass(1),
+					stages: (PipelineStage::COLOR_ATTACHMENT_OUTPUT |
+						PipelineStage::LATE_FRAGMENT_TESTS)..
+						PipelineStage::FRAGMENT_SHADER,
+					accesses: (Access::COLOR_ATTACHMENT_WRITE |
+						Access::DEPTH_STENCIL_ATTACHMENT_WRITE)..
+						Access::INPUT_ATTACHMENT_READ,
+				});
+			}
 
-			unsafe {
+			let pass = unsafe {
 				device
-					.create_render_pass(
-						&[color_attachment, depth_attachment],
-						&[subpass],
-						&[dependency],
-					)
+					.create_render_pass(&attachments, &subpasses, &dependencies)
 					.unwrap()
-			}
+			};
+			pass
 		};
 		RenderPass {
+			data,
 			swapchain,
 			pass: MaybeUninit::new(render_pass),
+			config,
+			color_format,
+			depth_format,
+		}
+	}
+
+	pub fn config(&self) -> &RenderPassConfig { &self.config }
+
+	pub fn color_format(&self) -> Format { self.color_format }
+
+	pub fn depth_format(&self) -> Format { self.depth_format }
+
+	/// Two when the config has input attachments, one otherwise.
+	pub fn subpass_count(&self) -> usize {
+		if self.config.inputs.is_empty() {
+			1
+		} else {
+			2
 		}
 	}
 
@@ -127,6 +341,32 @@ impl<'a> RenderPass<'a> {
 		FrameBuffer::create_same_size(self, views, size)
 	}
 
+	/// Creates one framebuffer per swapchain image for a multisampled pass,
+	/// resolving `color` into the swapchain image.
+	pub fn create_framebuffer_resolved(&self, color: &ImageView, depth: &ImageView) -> FrameBuffer {
+		assert!(
+			self.config.resolve.is_some(),
+			"Render pass was not created with a resolve attachment"
+		);
+		let (color_idx, depth_idx, resolve_idx) = self.config.attachment_indices();
+		let resolve_idx = resolve_idx.unwrap();
+		let swapchain = self
+			.swapchain
+			.expect("Resolved framebuffers need a swapchain render pass");
+		let views = swapchain
+			.image_views
+			.iter()
+			.map(|iv| {
+				let mut views = vec![color, depth, iv];
+				views[color_idx] = color;
+				views[depth_idx] = depth;
+				views[resolve_idx] = iv;
+				views
+			})
+			.collect::<Vec<_>>();
+		FrameBuffer::create_same_size(self, &views, swapchain.dims)
+	}
+
 	pub fn create_framebuffer<'b>(
 		&self,
 		views: &'b [Vec<&ImageView>],
@@ -135,12 +375,12 @@ impl<'a> RenderPass<'a> {
 		FrameBuffer::create(self, views, sizes)
 	}
 
-	pub(crate) fn make_subpass<'b>(&'a self) -> Subpass<'b, Backend>
+	pub(crate) fn make_subpass<'b>(&'a self, index: usize) -> Subpass<'b, Backend>
 	where
 		'a: 'b,
 	{
 		Subpass {
-			index: 0,
+			index,
 			main_pass: unsafe { self.pass.get_ref() },
 		}
 	}
@@ -149,6 +389,8 @@ impl<'a> RenderPass<'a> {
 		unsafe { self.pass.get_ref() }
 	}
 
+	/// `specialization` sets SPIR-V specialization constants per stage, such as
+	/// a maximum light count. Pass `PipeSpecialization::default()` for none.
 	pub fn create_pipeline<
 		Vertex: VertexInfo,
 		Uniforms: UniformInfo,
@@ -158,14 +400,15 @@ impl<'a> RenderPass<'a> {
 		&'a self,
 		shader: &'a Shader<'a, Vertex, Uniforms, Index, Constants>,
 		specialization: PipeSpecialization,
-	) -> Pipeline<'a, Vertex, Uniforms, Index, Constants> {
-		Pipeline::create(self, shader, specialization)
+		config: PipelineConfig,
+	) -> Result<Pipeline<'a, Vertex, Uniforms, Index, Constants>, PipelineError> {
+		Pipeline::create(self, shader, specialization, config)
 	}
 }
 
 impl<'a> Drop for RenderPass<'a> {
 	fn drop(&mut self) {
-		let device = self.swapchain.data.device();
+		let device = self.data.device();
 		unsafe {
 			device.destroy_render_pass(MaybeUninit::take(&mut self.pass));
 		}
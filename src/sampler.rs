@@ -6,7 +6,12 @@ use crate::{
 use std::mem::MaybeUninit;
 
 use gfx_hal::{
-	image::SamplerInfo,
+	image::{
+		Filter,
+		SamplerInfo,
+		WrapMode,
+	},
+	pso::Comparison,
 	Device,
 };
 
@@ -26,6 +31,15 @@ impl<'a> Sampler<'a> {
 		}
 	}
 
+	/// Creates a depth comparison sampler for PCF shadows. Bind it with the
+	/// depth texture as a `DescriptorType::CombinedImageSampler` and sample it
+	/// through a `sampler2DShadow` in the shader rather than a `sampler2D`.
+	pub fn create_comparison(data: &'a HALData, compare_op: Comparison) -> Sampler {
+		let mut info = SamplerInfo::new(Filter::Linear, WrapMode::Clamp);
+		info.comparison = Some(compare_op);
+		Self::create(data, info)
+	}
+
 	pub fn sampler(&self) -> &<Backend as gfx_hal::Backend>::Sampler {
 		unsafe { self.sampler.get_ref() }
 	}
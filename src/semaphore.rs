@@ -13,6 +13,13 @@ pub struct Semaphore<'a> {
 	semaphore: MaybeUninit<<Backend as gfx_hal::Backend>::Semaphore>,
 }
 
+/// Pre-allocated semaphores, e.g. the acquire and present semaphores of
+/// every frame in flight.
+pub struct SemaphorePool<'a> {
+	semaphores: Vec<Semaphore<'a>>,
+	next: usize,
+}
+
 impl<'a> Semaphore<'a> {
 	pub(crate) fn create(data: &'a HALData) -> Semaphore<'a> {
 		println!("Creating Semaphore");
@@ -32,6 +39,30 @@ impl<'a> Semaphore<'a> {
 	}
 }
 
+impl<'a> SemaphorePool<'a> {
+	pub fn create(data: &'a HALData, n: usize) -> SemaphorePool<'a> {
+		assert!(n > 0, "A semaphore pool needs at least one semaphore");
+		SemaphorePool {
+			semaphores: (0..n).map(|_| Semaphore::create(data)).collect(),
+			next: 0,
+		}
+	}
+
+	pub fn get(&self, idx: usize) -> &Semaphore<'a> { &self.semaphores[idx] }
+
+	/// Returns the next semaphore in the ring, e.g. for
+	/// `Swapchain::acquire_next_image`.
+	pub fn acquire_sem(&mut self) -> &mut Semaphore<'a> {
+		let idx = self.next;
+		self.next = (idx + 1) % self.semaphores.len();
+		&mut self.semaphores[idx]
+	}
+
+	pub fn len(&self) -> usize { self.semaphores.len() }
+
+	pub fn is_empty(&self) -> bool { self.semaphores.is_empty() }
+}
+
 impl<'a> Drop for Semaphore<'a> {
 	fn drop(&mut self) {
 		let device = self.data.device();
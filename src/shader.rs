@@ -1,10 +1,20 @@
 use std::{
+	cell::{
+		Cell,
+		UnsafeCell,
+	},
 	iter::once,
 	marker::PhantomData,
 	mem::MaybeUninit,
 };
 
+use byteorder::{
+	BigEndian as BE,
+	ByteOrder,
+	LittleEndian as LE,
+};
 use gfx_hal::{
+	device::ShaderError,
 	format::Format,
 	pso::{
 		AttributeDesc,
@@ -20,6 +30,7 @@ use gfx_hal::{
 		VertexBufferDesc,
 	},
 	Device,
+	Features,
 	IndexType as HALIndexType,
 };
 
@@ -30,6 +41,8 @@ use crate::{
 	HALData,
 };
 
+pub use villkiss_derive::PushConstantInfo;
+
 pub struct Shader<
 	'a,
 	Vertex: VertexInfo,
@@ -38,7 +51,8 @@ pub struct Shader<
 	Constants: PushConstantInfo,
 > {
 	pub(crate) data: &'a HALData,
-	pub(crate) mods: MaybeUninit<ShaderMods>,
+	pub(crate) mods: MaybeUninit<UnsafeCell<ShaderMods>>,
+	generation: Cell<u64>,
 	pub(crate) vertex_desc: VertexBufferDesc,
 	pub(crate) attribute_descs: Vec<AttributeDesc>,
 	pub(crate) layout_bindings: Vec<DescriptorSetLayoutBinding>,
@@ -57,6 +71,47 @@ pub struct ShaderSet<T> {
 	pub fragment: Option<T>,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InvalidSpirv {
+	NotWordAligned,
+	TooShort,
+	BadMagic,
+	BadVersion(u32),
+}
+
+#[derive(Debug)]
+pub enum ShaderReloadError {
+	MissingVertexShader,
+	StageMismatch,
+	/// Only checked in debug builds.
+	InvalidSpirv(InvalidSpirv),
+	/// The driver rejected a module. Modules created before it are destroyed.
+	ModuleCreation(ShaderError),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShaderValidationError {
+	MissingVertexShader,
+	/// Rasterization pipelines always need a fragment shader.
+	MissingFragmentShader,
+	/// Only one of the hull and domain shaders was given.
+	IncompleteTessellation,
+	TessellationUnsupported,
+	/// The vertex attribute formats don't add up to the vertex stride, see
+	/// `check_vertex_layout`.
+	VertexLayoutMismatch {
+		attribute_size: u32,
+		stride: u32,
+	},
+	/// Only checked in debug builds.
+	InvalidSpirv(InvalidSpirv),
+	ModuleCreation(ShaderError),
+}
+
+/// Every problem found while validating the stages passed to `Shader::create`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderErrors(pub Vec<ShaderValidationError>);
+
 pub type ShaderModData<'a> = ShaderSet<&'a [u8]>;
 type ShaderMods = ShaderSet<<Backend as gfx_hal::Backend>::ShaderModule>;
 
@@ -77,12 +132,37 @@ pub trait VertexInfo: Copy + Clone {
 	const STRIDE: u32;
 }
 
+/// Fails if the attribute formats of `V` do not add up to its stride, which
+/// happens when `#[repr(C)]` pads the struct and the attribute offsets
+/// computed from the formats no longer line up with the fields. Runs as part
+/// of `Shader::create`; structs from `vertex!` are also checked for padding
+/// at compile time.
+pub fn check_vertex_layout<V: VertexInfo>() -> Result<(), ShaderValidationError> {
+	let attribute_size = V::ATTRIBUTES
+		.iter()
+		.map(|format| (format.surface_desc().bits / 8) as u32)
+		.sum::<u32>();
+	if attribute_size == V::STRIDE {
+		Ok(())
+	} else {
+		Err(ShaderValidationError::VertexLayoutMismatch {
+			attribute_size,
+			stride: V::STRIDE,
+		})
+	}
+}
+
 pub trait UniformInfo {
 	const UNIFORMS: &'static [UniformInfoData];
+	/// Names of the entries in `UNIFORMS`, in the same order. Empty when the
+	/// bindings are unnamed.
+	const NAMES: &'static [&'static str] = &[];
 }
 
 pub struct UniformInfoData {
 	pub stage: ShaderStageFlags,
+	/// Compute shaders writing to an image need `DescriptorType::StorageImage`,
+	/// bound through `ImageView::storage_descriptor`.
 	pub uniform_type: DescriptorType,
 	pub count: DescriptorArrayIndex,
 	pub mutable: bool,
@@ -109,16 +189,33 @@ impl<
 	pub(crate) fn create<'b>(
 		data: &'a HALData,
 		shaders: ShaderModData<'b>,
-	) -> Shader<'a, Vertex, Uniforms, Index, Constants> {
+	) -> Result<Shader<'a, Vertex, Uniforms, Index, Constants>, ShaderErrors> {
 		assert!(
 			std::mem::size_of::<Constants>() % 4 == 0,
 			"Push constants must either be empty, or have a size divisible by 4"
 		);
 
+		let mut errors = shaders.validate(data.features());
+		if let Err(err) = check_vertex_layout::<Vertex>() {
+			errors.push(err);
+		}
+		if !errors.is_empty() {
+			return Err(ShaderErrors(errors));
+		}
+
 		println!("Creating Shader");
 		let device = data.device();
 
-		let mods = shaders.make_mods(device);
+		let mods = shaders.make_mods(device).map_err(|err| {
+			ShaderErrors(vec![match err {
+				ShaderReloadError::ModuleCreation(err) =>
+					ShaderValidationError::ModuleCreation(err),
+				ShaderReloadError::InvalidSpirv(err) => ShaderValidationError::InvalidSpirv(err),
+				ShaderReloadError::MissingVertexShader =>
+					ShaderValidationError::MissingVertexShader,
+				ShaderReloadError::StageMismatch => unreachable!(),
+			}])
+		})?;
 
 		let push_constant_stages = Constants::STAGES
 			.iter()
@@ -162,30 +259,12 @@ impl<
 			rate: 0,
 		};
 
-		let attribute_descs = {
-			let mut offset = 0;
-			Vertex::ATTRIBUTES
-				.iter()
-				.enumerate()
-				.map(|(location, format)| {
-					let location = location as u32;
-					let attr = AttributeDesc {
-						location,
-						binding: 0,
-						element: Element {
-							format: *format,
-							offset,
-						},
-					};
-					offset += (format.surface_desc().bits / 8) as u32;
-					attr
-				})
-				.collect::<Vec<_>>()
-		};
+		let attribute_descs = make_attributes(0, 0, Vertex::ATTRIBUTES);
 
-		Shader {
+		Ok(Shader {
 			data,
-			mods: MaybeUninit::new(mods),
+			mods: MaybeUninit::new(UnsafeCell::new(mods)),
+			generation: Cell::new(0),
 			vertex_desc,
 			attribute_descs,
 			layout_bindings,
@@ -193,10 +272,55 @@ impl<
 			pipeline_layout: MaybeUninit::new(pipe_layout),
 			push_constant_stages,
 			phantom: PhantomData,
+		})
+	}
+
+	pub fn bindings(&self) -> &[DescriptorSetLayoutBinding] { &self.layout_bindings }
+
+	pub fn binding_count(&self) -> usize { self.layout_bindings.len() }
+
+	pub fn binding_type(&self, idx: usize) -> DescriptorType { self.layout_bindings[idx].ty }
+
+	pub fn binding_name(&self, idx: usize) -> Option<&'static str> {
+		Uniforms::NAMES.get(idx).cloned()
+	}
+
+	pub fn has_tessellation(&self) -> bool { self.mods().hull.is_some() }
+
+	pub fn generation(&self) -> u64 { self.generation.get() }
+
+	pub fn push_constant_size(&self) -> u32 { Constants::SIZE }
+
+	pub fn push_constant_stages(&self) -> ShaderStageFlags { self.push_constant_stages }
+
+	/// Swaps the shader modules while keeping the descriptor and pipeline
+	/// layouts. Pipelines built from this shader become stale and must be
+	/// rebuilt with `Pipeline::refresh`.
+	pub fn reload_modules(&self, new_mods: ShaderModData) -> Result<(), ShaderReloadError> {
+		if new_mods.vertex.is_none() {
+			return Err(ShaderReloadError::MissingVertexShader);
+		}
+		let old = self.mods();
+		if new_mods.hull.is_some() != old.hull.is_some() ||
+			new_mods.domain.is_some() != old.domain.is_some() ||
+			new_mods.geometry.is_some() != old.geometry.is_some() ||
+			new_mods.fragment.is_some() != old.fragment.is_some()
+		{
+			return Err(ShaderReloadError::StageMismatch);
 		}
+
+		println!("Reloading Shader");
+		let device = self.data.device();
+		let mods = new_mods.make_mods(device)?;
+		// Pipelines only borrow the modules while they are being created, so no
+		// reference into the old set can be alive here.
+		let old = unsafe { std::mem::replace(&mut *self.mods.get_ref().get(), mods) };
+		old.man_drop(device);
+		self.generation.update(|g| g + 1);
+		Ok(())
 	}
 
-	pub(crate) fn layout_bindings(&self) -> &[DescriptorSetLayoutBinding] { &self.layout_bindings }
+	fn mods(&self) -> &ShaderMods { unsafe { &*self.mods.get_ref().get() } }
 
 	pub fn pipe_layout(&self) -> &<Backend as gfx_hal::Backend>::PipelineLayout {
 		unsafe { self.pipeline_layout.get_ref() }
@@ -229,7 +353,7 @@ impl<
 	where
 		'a: 'b,
 	{
-		unsafe { self.mods.get_ref() }.make_entry_points(specialization)
+		self.mods().make_entry_points(specialization)
 	}
 }
 
@@ -244,7 +368,9 @@ impl<
 	fn drop(&mut self) {
 		let device = self.data.device();
 		unsafe {
-			MaybeUninit::take(&mut self.mods).man_drop(device);
+			MaybeUninit::take(&mut self.mods)
+				.into_inner()
+				.man_drop(device);
 
 			device.destroy_descriptor_set_layout(MaybeUninit::take(&mut self.descriptor_layout));
 			device.destroy_pipeline_layout(MaybeUninit::take(&mut self.pipeline_layout));
@@ -253,27 +379,136 @@ impl<
 	}
 }
 
-impl ShaderModData<'_> {
-	fn make_mods(self, device: &<Backend as gfx_hal::Backend>::Device) -> ShaderMods {
-		unsafe {
-			ShaderMods {
-				vertex: Some(
-					device
-						.create_shader_module(
-							self.vertex.expect("All shaders must have a Vertex shader"),
-						)
-						.unwrap(),
-				),
-				hull: self.hull.map(|h| device.create_shader_module(h).unwrap()),
-				domain: self.domain.map(|h| device.create_shader_module(h).unwrap()),
-				geometry: self
-					.geometry
-					.map(|h| device.create_shader_module(h).unwrap()),
-				fragment: self
-					.fragment
-					.map(|h| device.create_shader_module(h).unwrap()),
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const SPIRV_HEADER_WORDS: usize = 5;
+
+/// Checks the SPIR-V header: word alignment, the magic number (in either
+/// byte order), and a `1.x` version word. The generator and bound words are
+/// only required to be present.
+pub fn validate_spirv(bytes: &[u8]) -> Result<(), InvalidSpirv> {
+	if bytes.len() % 4 != 0 {
+		return Err(InvalidSpirv::NotWordAligned);
+	}
+	if bytes.len() < SPIRV_HEADER_WORDS * 4 {
+		return Err(InvalidSpirv::TooShort);
+	}
+	let read_word: fn(&[u8]) -> u32 = if LE::read_u32(bytes) == SPIRV_MAGIC {
+		LE::read_u32
+	} else if BE::read_u32(bytes) == SPIRV_MAGIC {
+		BE::read_u32
+	} else {
+		return Err(InvalidSpirv::BadMagic);
+	};
+	let version = read_word(&bytes[4..]);
+	let major = (version >> 16) & 0xFF;
+	if major != 1 || version & 0xFF00_00FF != 0 {
+		return Err(InvalidSpirv::BadVersion(version));
+	}
+	Ok(())
+}
+
+pub(crate) fn make_attributes(
+	binding: u32,
+	first_location: u32,
+	formats: &[Format],
+) -> Vec<AttributeDesc> {
+	let mut offset = 0;
+	formats
+		.iter()
+		.enumerate()
+		.map(|(location, format)| {
+			let location = first_location + location as u32;
+			let attr = AttributeDesc {
+				location,
+				binding,
+				element: Element {
+					format: *format,
+					offset,
+				},
+			};
+			offset += (format.surface_desc().bits / 8) as u32;
+			attr
+		})
+		.collect::<Vec<_>>()
+}
+
+impl<'a> ShaderModData<'a> {
+	/// The vertex and fragment stages most shaders consist of. Works directly
+	/// with `include_bytes!`.
+	pub fn vert_frag(vertex: &'a [u8], fragment: &'a [u8]) -> Self {
+		ShaderModData {
+			vertex: Some(vertex),
+			fragment: Some(fragment),
+			..Default::default()
+		}
+	}
+
+	fn validate(&self, features: Features) -> Vec<ShaderValidationError> {
+		let mut errors = Vec::new();
+		if self.vertex.is_none() {
+			errors.push(ShaderValidationError::MissingVertexShader);
+		}
+		if self.fragment.is_none() {
+			errors.push(ShaderValidationError::MissingFragmentShader);
+		}
+		if self.hull.is_some() != self.domain.is_some() {
+			errors.push(ShaderValidationError::IncompleteTessellation);
+		}
+		if (self.hull.is_some() || self.domain.is_some()) &&
+			!features.contains(Features::TESSELLATION_SHADER)
+		{
+			errors.push(ShaderValidationError::TessellationUnsupported);
+		}
+		errors
+	}
+
+	fn make_mods(
+		&self,
+		device: &<Backend as gfx_hal::Backend>::Device,
+	) -> Result<ShaderMods, ShaderReloadError> {
+		let mut mods = ShaderMods {
+			vertex: None,
+			hull: None,
+			domain: None,
+			geometry: None,
+			fragment: None,
+		};
+		match self.create_mods_into(&mut mods, device) {
+			Ok(()) => Ok(mods),
+			Err(err) => {
+				mods.man_drop(device);
+				Err(err)
+			},
+		}
+	}
+
+	fn create_mods_into(
+		&self,
+		mods: &mut ShaderMods,
+		device: &<Backend as gfx_hal::Backend>::Device,
+	) -> Result<(), ShaderReloadError> {
+		let create = |bytes: &[u8]| -> Result<_, ShaderReloadError> {
+			#[cfg(debug_assertions)]
+			{
+				validate_spirv(bytes).map_err(ShaderReloadError::InvalidSpirv)?;
 			}
+			unsafe { device.create_shader_module(bytes) }.map_err(ShaderReloadError::ModuleCreation)
+		};
+		let vertex = self.vertex.ok_or(ShaderReloadError::MissingVertexShader)?;
+		mods.vertex = Some(create(vertex)?);
+		if let Some(bytes) = self.hull {
+			mods.hull = Some(create(bytes)?);
+		}
+		if let Some(bytes) = self.domain {
+			mods.domain = Some(create(bytes)?);
 		}
+		if let Some(bytes) = self.geometry {
+			mods.geometry = Some(create(bytes)?);
+		}
+		if let Some(bytes) = self.fragment {
+			mods.fragment = Some(create(bytes)?);
+		}
+		Ok(())
 	}
 }
 
@@ -386,6 +621,9 @@ macro_rules! vertex {
 			];
 			const STRIDE: u32 = std::mem::size_of::<$name>() as u32;
 		}
+		// Fails to compile with an array length mismatch when `#[repr(C)]`
+		// pads the struct, since the stride would then exceed the fields.
+		const _: [(); 0] = [(); std::mem::size_of::<$name>() - (0 $(+ std::mem::size_of::<$vert_type>())*)];
 	};
 	//No trailing comma
 	(
@@ -409,6 +647,22 @@ macro_rules! vertex {
 
 #[macro_export]
 macro_rules! descriptor {
+	//Named bindings
+	(
+		$vis:vis $name: ident = {
+			$($ui_name:literal => $ui:expr),* $(,)?
+		};
+	) => {
+		$vis struct $name;
+		impl ::villkiss::shader::UniformInfo for $name {
+			const UNIFORMS: &'static [shader::UniformInfoData] = &[
+				$($ui,)*
+			];
+			const NAMES: &'static [&'static str] = &[
+				$($ui_name,)*
+			];
+		}
+	};
 	//Actual macro
 	(
 		$vis:vis $name: ident = {
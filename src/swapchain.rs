@@ -14,7 +14,10 @@ use gfx_hal::{
 		Kind,
 		WrapMode,
 	},
-	window::Extent2D,
+	window::{
+		CompositeAlpha,
+		Extent2D,
+	},
 	AcquireError,
 	Backbuffer,
 	Device,
@@ -32,7 +35,9 @@ use crate::ImageView;
 use crate::{
 	buffer::StagingBuffer,
 	gfx_back::Backend,
+	renderpass::RenderPassConfig,
 	texture::{
+		AnisotropyLevel,
 		MipMaps,
 		Texture,
 		TextureInfo,
@@ -43,9 +48,55 @@ use crate::{
 	Semaphore,
 };
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NeedsRecreation;
+
+/// How the swapchain images are composited with the rest of the desktop.
+/// Transparent windows need `prefer_opaque: false`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CompositeAlphaPreference {
+	pub prefer_opaque: bool,
+	pub allow_inherit: bool,
+}
+
+impl Default for CompositeAlphaPreference {
+	fn default() -> Self {
+		CompositeAlphaPreference {
+			prefer_opaque: true,
+			allow_inherit: true,
+		}
+	}
+}
+
+impl CompositeAlphaPreference {
+	fn select(&self, supported: CompositeAlpha) -> CompositeAlpha {
+		let blended = [
+			CompositeAlpha::PREMULTIPLIED,
+			CompositeAlpha::POSTMULTIPLIED,
+		];
+		let mut order = Vec::with_capacity(4);
+		if self.prefer_opaque {
+			order.push(CompositeAlpha::OPAQUE);
+			order.extend_from_slice(&blended);
+		} else {
+			order.extend_from_slice(&blended);
+			order.push(CompositeAlpha::OPAQUE);
+		}
+		if self.allow_inherit {
+			order.push(CompositeAlpha::INHERIT);
+		}
+		order
+			.into_iter()
+			.find(|alpha| supported.contains(*alpha))
+			.expect("The surface supports none of the allowed composite alpha modes")
+	}
+}
+
 pub struct Swapchain<'a> {
 	pub(crate) data: &'a HALData,
 	pub(crate) dims: Extent,
+	pub(crate) present_mode: PresentMode,
+	composite_alpha: CompositeAlphaPreference,
 	pub(crate) swapchain: MaybeUninit<RefCell<<Backend as gfx_hal::Backend>::Swapchain>>,
 	pub(crate) backbuffer: Backbuffer<Backend>,
 	//	#[cfg(not(feature = "gl"))]
@@ -56,8 +107,69 @@ pub struct Swapchain<'a> {
 }
 
 impl<'a> Swapchain<'a> {
-	pub(crate) fn create<'b>(data: &'a HALData, staging_buf: &'b StagingBuffer) -> Swapchain<'a> {
+	pub(crate) fn create<'b>(
+		data: &'a HALData,
+		staging_buf: &'b StagingBuffer,
+		composite_alpha: CompositeAlphaPreference,
+	) -> Swapchain<'a> {
 		println!("Creating Swapchain");
+		let (dims, swapchain, backbuffer, image_views, depth_tex) = Self::create_parts(
+			data,
+			staging_buf,
+			PresentMode::Mailbox,
+			composite_alpha,
+			None,
+		);
+		Swapchain {
+			data,
+			dims,
+			present_mode: PresentMode::Mailbox,
+			composite_alpha,
+			swapchain: MaybeUninit::new(RefCell::new(swapchain)),
+			backbuffer,
+			//			#[cfg(not(feature = "gl"))]
+			image_views,
+			depth_tex,
+			/*			#[cfg(feature = "gl")]
+			 *			fbo, */
+		}
+	}
+
+	/// Rebuilds the swapchain, its image views and the depth texture with the
+	/// current present mode. Render passes and framebuffers created from the
+	/// old swapchain must be dropped first.
+	pub fn recreate<'b>(&mut self, staging_buf: &'b StagingBuffer) {
+		println!("Recreating Swapchain");
+		let data = self.data;
+		data.wait_idle();
+		let old = RefCell::into_inner(MaybeUninit::take(&mut self.swapchain));
+		let (dims, swapchain, backbuffer, image_views, depth_tex) = Self::create_parts(
+			data,
+			staging_buf,
+			self.present_mode,
+			self.composite_alpha,
+			Some(old),
+		);
+		self.dims = dims;
+		self.swapchain = MaybeUninit::new(RefCell::new(swapchain));
+		self.backbuffer = backbuffer;
+		self.image_views = image_views;
+		self.depth_tex = depth_tex;
+	}
+
+	fn create_parts<'b>(
+		data: &'a HALData,
+		staging_buf: &'b StagingBuffer,
+		present_mode: PresentMode,
+		composite_alpha: CompositeAlphaPreference,
+		old_swapchain: Option<<Backend as gfx_hal::Backend>::Swapchain>,
+	) -> (
+		Extent,
+		<Backend as gfx_hal::Backend>::Swapchain,
+		Backbuffer<Backend>,
+		Vec<ImageView<'a>>,
+		Texture<'a>,
+	) {
 		let device = data.device();
 		let (capabilities, formats, _) = data
 			.surface()
@@ -78,20 +190,28 @@ impl<'a> Swapchain<'a> {
 				height: 600,
 			},
 		)
-		.with_mode(PresentMode::Mailbox);
+		.with_mode(present_mode);
+		let swap_config = SwapchainConfig {
+			composite_alpha: composite_alpha.select(capabilities.composite_alpha),
+			..swap_config
+		};
 		let dims = swap_config.extent.to_extent();
 		let (swapchain, backbuffer) = unsafe {
 			device
-				.create_swapchain(&mut data.surface().borrow_mut(), swap_config, None)
+				.create_swapchain(&mut data.surface().borrow_mut(), swap_config, old_swapchain)
 				.unwrap()
 		};
 		let depth_tex = data.create_texture(
 			TextureInfo {
 				kind: Kind::D2(dims.width, dims.height, 1, 1),
-				format: Format::D32FloatS8Uint,
+				format: data.depth_format(),
 				mipmaps: MipMaps::None,
 				pixels: None,
 				wrap_mode: (WrapMode::Border, WrapMode::Border, WrapMode::Border),
+				convert_on_upload: None,
+				samples: 1,
+				anisotropy: AnisotropyLevel::Off,
+				mutable_format: false,
 			},
 			staging_buf,
 		);
@@ -118,17 +238,7 @@ impl<'a> Swapchain<'a> {
 			Backbuffer::Framebuffer(fbo) => fbo,
 			_ => panic!("Opengl backend gave images!"),
 		};
-		Swapchain {
-			data,
-			dims,
-			swapchain: MaybeUninit::new(RefCell::new(swapchain)),
-			backbuffer,
-			//			#[cfg(not(feature = "gl"))]
-			image_views,
-			depth_tex,
-			/*			#[cfg(feature = "gl")]
-			 *			fbo, */
-		}
+		(dims, swapchain, backbuffer, image_views, depth_tex)
 	}
 
 	pub fn acquire_next_image<'b>(&'b self, sem: &'b mut Semaphore) -> Result<u32, AcquireError> {
@@ -150,7 +260,33 @@ impl<'a> Swapchain<'a> {
 
 	pub fn dims(&self) -> &Extent { &self.dims }
 
-	pub fn create_renderpass(&self) -> RenderPass { RenderPass::create(self) }
+	pub fn present_mode(&self) -> PresentMode { self.present_mode }
+
+	/// Attempts to switch the present mode without recreating the swapchain.
+	/// gfx-hal has no in-place path for this yet, so any actual change is
+	/// stored and returns `Err(NeedsRecreation)`. The new mode is applied by
+	/// the next `recreate`.
+	pub fn set_present_mode(&mut self, mode: PresentMode) -> Result<(), NeedsRecreation> {
+		if mode == self.present_mode {
+			return Ok(());
+		}
+		let (_, _, present_modes) = self
+			.data
+			.surface()
+			.borrow()
+			.compatibility(&self.data.adapter().physical_device);
+		assert!(
+			present_modes.contains(&mode),
+			"The surface does not support present mode {:?}",
+			mode
+		);
+		self.present_mode = mode;
+		Err(NeedsRecreation)
+	}
+
+	pub fn create_renderpass(&self, config: RenderPassConfig) -> RenderPass {
+		RenderPass::create(self, config)
+	}
 }
 
 impl<'a> Drop for Swapchain<'a> {
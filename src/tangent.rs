@@ -0,0 +1,150 @@
+use cgmath::{
+	InnerSpace,
+	Vector3,
+	Zero,
+};
+
+use crate::shader::VertexInfo;
+
+/// Vertices that carry a tangent, declared with `vertex!` as e.g.
+/// `tangent: [f32; 4] as Rgba32Float`.
+pub trait HasTangent: VertexInfo {
+	fn position(&self) -> [f32; 3];
+	fn normal(&self) -> [f32; 3];
+	fn uv(&self) -> [f32; 2];
+	/// `w` is the handedness of the bitangent, `1.0` or `-1.0`.
+	fn set_tangent(&mut self, tangent: [f32; 4]);
+}
+
+/// Computes per-vertex tangents for an indexed triangle list using Lengyel's
+/// method. The bitangent is `cross(normal, tangent.xyz) * tangent.w`.
+pub fn generate_tangents<V: HasTangent, I: Copy + Into<u32>>(vertices: &mut [V], indices: &[I]) {
+	assert_eq!(indices.len() % 3, 0, "Tangents need a triangle list");
+	let mut tangents = vec![Vector3::zero(); vertices.len()];
+	let mut bitangents = vec![Vector3::zero(); vertices.len()];
+
+	for triangle in indices.chunks(3) {
+		let idx = [
+			triangle[0].into() as usize,
+			triangle[1].into() as usize,
+			triangle[2].into() as usize,
+		];
+		let pos = idx
+			.iter()
+			.map(|&i| Vector3::from(vertices[i].position()))
+			.collect::<Vec<_>>();
+		let uv = idx.iter().map(|&i| vertices[i].uv()).collect::<Vec<_>>();
+
+		let (e1, e2) = (pos[1] - pos[0], pos[2] - pos[0]);
+		let (s1, t1) = (uv[1][0] - uv[0][0], uv[1][1] - uv[0][1]);
+		let (s2, t2) = (uv[2][0] - uv[0][0], uv[2][1] - uv[0][1]);
+		let det = s1 * t2 - s2 * t1;
+		if det.abs() <= std::f32::EPSILON {
+			continue;
+		}
+		let r = 1.0 / det;
+		let tangent = (e1 * t2 - e2 * t1) * r;
+		let bitangent = (e2 * s1 - e1 * s2) * r;
+		for &i in &idx {
+			tangents[i] += tangent;
+			bitangents[i] += bitangent;
+		}
+	}
+
+	for (i, vertex) in vertices.iter_mut().enumerate() {
+		let normal = Vector3::from(vertex.normal());
+		// Gram-Schmidt orthogonalize against the normal.
+		let tangent = tangents[i] - normal * normal.dot(tangents[i]);
+		let tangent = if tangent.magnitude2() > 0.0 {
+			tangent.normalize()
+		} else {
+			Vector3::zero()
+		};
+		let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+			-1.0
+		} else {
+			1.0
+		};
+		vertex.set_tangent([tangent.x, tangent.y, tangent.z, handedness]);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use gfx_hal::format::Format;
+
+	use super::*;
+
+	#[derive(Debug, Clone, Copy)]
+	struct Vertex {
+		position: [f32; 3],
+		uv: [f32; 2],
+		tangent: [f32; 4],
+	}
+
+	impl VertexInfo for Vertex {
+		const ATTRIBUTES: &'static [Format] =
+			&[Format::Rgb32Float, Format::Rg32Float, Format::Rgba32Float];
+		const STRIDE: u32 = std::mem::size_of::<Vertex>() as u32;
+	}
+
+	impl HasTangent for Vertex {
+		fn position(&self) -> [f32; 3] { self.position }
+
+		fn normal(&self) -> [f32; 3] { [0.0, 0.0, 1.0] }
+
+		fn uv(&self) -> [f32; 2] { self.uv }
+
+		fn set_tangent(&mut self, tangent: [f32; 4]) { self.tangent = tangent; }
+	}
+
+	/// Unit quad in the XY plane facing +Z.
+	fn quad(uvs: [[f32; 2]; 4]) -> Vec<Vertex> {
+		let positions = [
+			[0.0, 0.0, 0.0],
+			[1.0, 0.0, 0.0],
+			[1.0, 1.0, 0.0],
+			[0.0, 1.0, 0.0],
+		];
+		positions
+			.iter()
+			.zip(uvs.iter())
+			.map(|(&position, &uv)| Vertex {
+				position,
+				uv,
+				tangent: [0.0; 4],
+			})
+			.collect()
+	}
+
+	const INDICES: [u16; 6] = [0, 1, 2, 0, 2, 3];
+
+	#[test]
+	fn tangent_follows_u() {
+		let mut vertices = quad([[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
+		generate_tangents(&mut vertices, &INDICES);
+		// U runs along +X and V along +Y, so cross(N, T) = +Y is the bitangent.
+		for vertex in &vertices {
+			assert_eq!(vertex.tangent, [1.0, 0.0, 0.0, 1.0]);
+		}
+	}
+
+	#[test]
+	fn mirrored_u_flips_handedness() {
+		let mut vertices = quad([[1.0, 0.0], [0.0, 0.0], [0.0, 1.0], [1.0, 1.0]]);
+		generate_tangents(&mut vertices, &INDICES);
+		// U runs along -X, while the bitangent stays +Y = -cross(N, T).
+		for vertex in &vertices {
+			assert_eq!(vertex.tangent, [-1.0, 0.0, 0.0, -1.0]);
+		}
+	}
+
+	#[test]
+	fn degenerate_uvs_are_skipped() {
+		let mut vertices = quad([[0.5, 0.5]; 4]);
+		generate_tangents(&mut vertices, &INDICES);
+		for vertex in &vertices {
+			assert_eq!(vertex.tangent, [0.0, 0.0, 0.0, 1.0]);
+		}
+	}
+}
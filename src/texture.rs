@@ -1,4 +1,5 @@
 use std::{
+	cell::Cell,
 	iter::once,
 	mem::MaybeUninit,
 	ops::Range,
@@ -6,6 +7,7 @@ use std::{
 
 use gfx_hal::{
 	self,
+	adapter::PhysicalDevice,
 	command::{
 		BufferImageCopy,
 		ImageBlit,
@@ -13,10 +15,12 @@ use gfx_hal::{
 	format::{
 		Aspects,
 		Format,
+		SurfaceType,
 	},
 	image::{
 		Access,
 		Anisotropic,
+		Extent,
 		Filter,
 		Kind,
 		Layout,
@@ -42,6 +46,7 @@ use gfx_hal::{
 	},
 	queue::Graphics,
 	Device,
+	Features,
 };
 
 use gfx_memory::{
@@ -56,12 +61,15 @@ use crate::{
 		inner::InnerBuffer,
 		StagingBuffer,
 	},
+	fence::FenceStatus,
 	gfx_back::Backend,
 	util::TakeExt,
 	CommandPool,
 	Fence,
+	FrameBuffer,
 	HALData,
 	ImageView,
+	RenderPass,
 	Sampler,
 };
 
@@ -69,9 +77,19 @@ pub struct Texture<'a> {
 	pub(crate) data: &'a HALData,
 	pub(crate) kind: ViewKind,
 	pub(crate) format: Format,
+	pub(crate) extent: Extent,
+	pub(crate) mip_levels: u8,
+	pub(crate) mipmaps: MipMaps,
+	pub(crate) layers: u16,
+	pub(crate) samples: u8,
 	pub(crate) image: MaybeUninit<<Backend as gfx_hal::Backend>::Image>,
 	pub(crate) block: MaybeUninit<<SmartAllocator<Backend> as MemoryAllocator<Backend>>::Block>,
 	pub(crate) view: ImageView<'a>,
+	pub(crate) stencil_view: Option<ImageView<'a>>,
+	pub(crate) mutable_format: bool,
+	/// Live views handed out by `create_view`, `create_reinterpreted_view` and
+	/// `face_framebuffer`.
+	pub(crate) extra_views: Cell<usize>,
 	pub(crate) sampler: Option<Sampler<'a>>,
 }
 
@@ -79,6 +97,9 @@ pub struct Texture<'a> {
 pub enum MipMaps {
 	PreExisting(u8),
 	Generate,
+	/// Allocates the full mip chain like `Generate`, but leaves the generation
+	/// to `Texture::generate_mipmaps_async`.
+	Deferred,
 	None,
 }
 
@@ -87,7 +108,7 @@ impl MipMaps {
 		match self {
 			MipMaps::PreExisting(i) => *i,
 			MipMaps::None => 1,
-			MipMaps::Generate =>
+			MipMaps::Generate | MipMaps::Deferred =>
 				f32::log(
 					u32::max(info.kind.extent().width, info.kind.extent().height) as f32,
 					2f32,
@@ -104,23 +125,266 @@ pub struct TextureInfo<'a> {
 	pub mipmaps: MipMaps,
 	pub pixels: Option<&'a [u8]>,
 	pub wrap_mode: (WrapMode, WrapMode, WrapMode),
+	/// Converts `pixels` on the CPU before they are uploaded. `to` must match
+	/// `format`.
+	pub convert_on_upload: Option<PixelConversion>,
+	/// Sample count of the image. Replaces the one in a `Kind::D2`; other
+	/// kinds only support 1.
+	pub samples: u8,
+	/// Clamped to what the device supports when the sampler is created.
+	pub anisotropy: AnisotropyLevel,
+	/// Creates the image with `ViewCapabilities::MUTABLE_FORMAT`, so it can
+	/// be viewed in other formats with `Texture::create_reinterpreted_view`.
+	pub mutable_format: bool,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DimensionMismatch {
+	/// Bytes needed for the requested dimensions.
+	pub expected: usize,
+	pub actual: usize,
+}
+
+/// Faces of a cube map, in the order of their layers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CubeFace {
+	PosX,
+	NegX,
+	PosY,
+	NegY,
+	PosZ,
+	NegZ,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AnisotropyLevel {
+	Off,
+	On(u8),
+}
+
+impl<'a> TextureInfo<'a> {
+	/// A sampled `Rgba8Srgb` texture with a generated mip chain, after checking
+	/// `pixels` holds exactly `width * height` texels.
+	pub fn from_rgba8(
+		pixels: &'a [u8],
+		width: u32,
+		height: u32,
+	) -> Result<TextureInfo<'a>, DimensionMismatch> {
+		Self::check_len(pixels, width as usize * height as usize * 4)?;
+		Ok(Self::rgba8(pixels, width, height, MipMaps::Generate))
+	}
+
+	/// Like `from_rgba8`, but `chain` holds `levels` mip levels packed back to
+	/// back, starting with the full size level.
+	pub fn from_rgba8_with_mips(
+		chain: &'a [u8],
+		width: u32,
+		height: u32,
+		levels: u8,
+	) -> Result<TextureInfo<'a>, DimensionMismatch> {
+		let expected = (0..levels)
+			.map(|level| {
+				let w = (width >> level).max(1);
+				let h = (height >> level).max(1);
+				w as usize * h as usize * 4
+			})
+			.sum();
+		Self::check_len(chain, expected)?;
+		let mipmaps = MipMaps::PreExisting(levels);
+		Ok(Self::rgba8(chain, width, height, mipmaps))
+	}
+
+	fn check_len(pixels: &[u8], expected: usize) -> Result<(), DimensionMismatch> {
+		if pixels.len() == expected {
+			Ok(())
+		} else {
+			Err(DimensionMismatch {
+				expected,
+				actual: pixels.len(),
+			})
+		}
+	}
+
+	fn rgba8(pixels: &'a [u8], width: u32, height: u32, mipmaps: MipMaps) -> TextureInfo<'a> {
+		TextureInfo {
+			kind: Kind::D2(width, height, 1, 1),
+			format: Format::Rgba8Srgb,
+			mipmaps,
+			pixels: Some(pixels),
+			wrap_mode: (WrapMode::Tile, WrapMode::Tile, WrapMode::Tile),
+			convert_on_upload: None,
+			samples: 1,
+			anisotropy: AnisotropyLevel::On(16),
+			mutable_format: false,
+		}
+	}
+}
+
+/// Collects textures so their uploads share one staging upload and one
+/// submission.
+pub struct StagingBatch<'a, 'b> {
+	data: &'a HALData,
+	infos: Vec<TextureInfo<'b>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UnsupportedSampleCount {
+	pub requested: u8,
+	/// Bitmask of the sample counts the format supports.
+	pub supported: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PixelConversion {
+	pub from: Format,
+	pub to: Format,
+}
+
+impl PixelConversion {
+	/// Supports `Rgb8 -> Rgba8`, filling alpha with 255, and `Rgba8 -> Bgra8`.
+	pub fn convert(&self, pixels: &[u8]) -> Vec<u8> {
+		let (from_surface, from_channel) = self.from.base_format();
+		let (to_surface, to_channel) = self.to.base_format();
+		if from_channel != to_channel {
+			println!(
+				"Warning: Converting {:?} to {:?} reinterprets {:?} data as {:?}",
+				self.from, self.to, from_channel, to_channel
+			);
+		}
+		// Every supported conversion produces 4 byte texels.
+		let (src_texel_size, convert_texel): (usize, fn(&[u8]) -> [u8; 4]) =
+			match (from_surface, to_surface) {
+				(SurfaceType::R8_G8_B8, SurfaceType::R8_G8_B8_A8) =>
+					(3, |rgb| [rgb[0], rgb[1], rgb[2], 255]),
+				(SurfaceType::R8_G8_B8_A8, SurfaceType::B8_G8_R8_A8) =>
+					(4, |rgba| [rgba[2], rgba[1], rgba[0], rgba[3]]),
+				_ => panic!(
+					"Unsupported pixel conversion {:?} -> {:?}",
+					self.from, self.to
+				),
+			};
+		assert_eq!(
+			pixels.len() % src_texel_size,
+			0,
+			"{} bytes of pixel data are not a whole number of {:?} texels",
+			pixels.len(),
+			self.from
+		);
+		let mut converted = Vec::with_capacity(pixels.len() / src_texel_size * 4);
+		for texel in pixels.chunks_exact(src_texel_size) {
+			converted.extend_from_slice(&convert_texel(texel));
+		}
+		converted
+	}
+}
+
+/// The levels a sampler may pick from a chain of `mip_levels`. The last level
+/// of the chain is `mip_levels - 1`.
+fn lod_range(mip_levels: u8) -> Range<f32> { 0.0..(mip_levels as f32 - 1.0) }
+
 impl<'a> Texture<'a> {
 	pub(crate) fn create<'b>(
 		data: &'a HALData,
 		info: TextureInfo<'b>,
 		staging_buf: &'b StagingBuffer,
 	) -> Texture<'a> {
+		Self::try_create(data, info, staging_buf).expect("Unsupported texture sample count")
+	}
+
+	pub(crate) fn try_create<'b>(
+		data: &'a HALData,
+		info: TextureInfo<'b>,
+		staging_buf: &'b StagingBuffer,
+	) -> Result<Texture<'a>, UnsupportedSampleCount> {
+		let texture = Self::allocate(data, info, info.pixels.is_some())?;
+		let command_pool = &staging_buf.command_pool;
+		let fence = &staging_buf.fence;
+		if let Some(pixels) = info.pixels {
+			let converted = Self::convert_pixels(&info, pixels);
+			staging_buf.upload(converted.as_ref().map_or(pixels, |c| c.as_slice()));
+		} else {
+			fence.wait_n_reset();
+		}
+		command_pool.single_submit(&[], &[], &fence, |cmd_buf| {
+			texture.record_upload(cmd_buf, &info, staging_buf.hal_buffer(), 0);
+		});
+		match info.mipmaps {
+			MipMaps::Generate => Self::gen_mipmaps(
+				texture.image(),
+				command_pool,
+				texture.extent,
+				texture.mip_levels,
+				texture.layers,
+				&fence,
+			),
+			_ => (),
+		}
+		fence.wait();
+		Ok(texture)
+	}
+
+	/// Creates a sampled color texture without uploading anything, e.g. to
+	/// fill it later with `upload_tiled`. Every level is left in
+	/// `Layout::ShaderReadOnlyOptimal`.
+	pub(crate) fn create_empty<'b>(
+		data: &'a HALData,
+		info: TextureInfo<'b>,
+		staging_buf: &'b StagingBuffer,
+	) -> Texture<'a> {
+		assert!(
+			info.pixels.is_none(),
+			"Empty textures are created without pixels"
+		);
+		let texture = Self::allocate(data, info, true).expect("Unsupported texture sample count");
+		let fence = &staging_buf.fence;
+		fence.wait_n_reset();
+		staging_buf
+			.command_pool
+			.single_submit(&[], &[], fence, |cmd_buf| {
+				for level in 0..texture.mip_levels {
+					Self::transition_image_layout(
+						cmd_buf,
+						texture.image(),
+						level,
+						0..texture.layers,
+						Layout::Undefined..Layout::TransferDstOptimal,
+					);
+					Self::transition_image_layout(
+						cmd_buf,
+						texture.image(),
+						level,
+						0..texture.layers,
+						Layout::TransferDstOptimal..Layout::ShaderReadOnlyOptimal,
+					);
+				}
+			});
+		fence.wait();
+		texture
+	}
+
+	/// Creates the image, its views and sampler without recording any
+	/// commands. The image is left in `Layout::Undefined`. `sampled` textures
+	/// get a color image and a sampler, others become depth attachments.
+	fn allocate(
+		data: &'a HALData,
+		mut info: TextureInfo,
+		sampled: bool,
+	) -> Result<Texture<'a>, UnsupportedSampleCount> {
+		info.kind = match info.kind {
+			Kind::D2(width, height, layers, _) => Kind::D2(width, height, layers, info.samples),
+			kind => {
+				assert_eq!(info.samples, 1, "Only 2D textures can be multisampled");
+				kind
+			},
+		};
 		println!("Creating Texture");
-		let device = data.device();
 		let extent = info.kind.extent();
-		let command_pool = &staging_buf.command_pool;
 		let mip_levels = info.mipmaps.levels(info);
-		let (usage, aspects, sampler) = if info.pixels.is_some() {
+		let layers = info.kind.num_layers();
+		let (usage, aspects, sampler) = if sampled {
 			let mut usage = Usage::TRANSFER_DST | Usage::SAMPLED;
 			match info.mipmaps {
-				MipMaps::Generate => usage |= Usage::TRANSFER_SRC,
+				MipMaps::Generate | MipMaps::Deferred => usage |= Usage::TRANSFER_SRC,
 				_ => (),
 			}
 			let aspects = Aspects::COLOR;
@@ -132,10 +396,13 @@ impl<'a> Texture<'a> {
 					mip_filter: Filter::Linear,
 					wrap_mode: info.wrap_mode,
 					lod_bias: 0f32.into(),
-					lod_range: 0f32.into()..((mip_levels as f32) / 8f32).into(),
+					lod_range: {
+						let range = lod_range(mip_levels);
+						range.start.into()..range.end.into()
+					},
 					comparison: None,
 					border: PackedColor(0x0),
-					anisotropic: Anisotropic::On(16),
+					anisotropic: Self::anisotropic(data, info.anisotropy),
 				},
 			));
 			(usage, aspects, sampler)
@@ -145,67 +412,32 @@ impl<'a> Texture<'a> {
 			let sampler = None;
 			(usage, aspects, sampler)
 		};
-		let (image, block) = Texture::image_block(data, &info, usage);
-		let fence = &staging_buf.fence;
-		info.pixels.map_or_else(
-			|| {
-				command_pool.single_submit(&[], &[], &fence, |cmd_buf| {
-					Self::transition_image_layout(
-						cmd_buf,
-						&image,
-						0,
-						Layout::Undefined..Layout::DepthStencilAttachmentOptimal,
-					);
-				})
-			},
-			|pixels| {
-				staging_buf.upload(pixels);
-				command_pool.single_submit(&[], &[], &fence, |cmd_buf| {
-					let range = match info.mipmaps {
-						MipMaps::PreExisting(i) => 0..i,
-						_ => 0..1,
-					};
-					for level in range {
-						let copy = BufferImageCopy {
-							buffer_offset: 0,
-							buffer_width: 0,
-							buffer_height: 0,
-							image_layers: SubresourceLayers {
-								aspects: Aspects::COLOR,
-								level,
-								layers: 0..1,
-							},
-							image_offset: Offset::ZERO,
-							image_extent: extent,
-						};
-						Self::transition_image_layout(
-							cmd_buf,
-							&image,
-							level,
-							Layout::Undefined..Layout::TransferDstOptimal,
-						);
-						unsafe {
-							cmd_buf.copy_buffer_to_image(
-								&staging_buf.hal_buffer(),
-								&image,
-								Layout::TransferDstOptimal,
-								once(copy),
-							);
-						}
-						Self::transition_image_layout(
-							cmd_buf,
-							&image,
-							level,
-							Layout::TransferDstOptimal..Layout::ShaderReadOnlyOptimal,
-						);
-					}
-				})
-			},
-		);
-		match info.mipmaps {
-			MipMaps::Generate => Self::gen_mipmaps(&image, command_pool, info, &fence),
-			_ => (),
+		let capabilities = if info.mutable_format {
+			ViewCapabilities::MUTABLE_FORMAT
+		} else {
+			ViewCapabilities::empty()
+		};
+		let supported = data
+			.adapter()
+			.physical_device
+			.image_format_properties(info.format, 2, Tiling::Optimal, usage, capabilities)
+			.map_or(0, |props| props.sample_count_mask);
+		// The mask has one bit per supported count, so only powers of two can
+		// match it exactly.
+		if !info.samples.is_power_of_two() || supported & info.samples != info.samples {
+			return Err(UnsupportedSampleCount {
+				requested: info.samples,
+				supported,
+			});
 		}
+		let (image, block) = Texture::image_block(
+			data,
+			info.kind,
+			mip_levels,
+			info.format,
+			usage,
+			capabilities,
+		);
 
 		let kind = match info.kind {
 			Kind::D1(_, _) => ViewKind::D1,
@@ -213,40 +445,203 @@ impl<'a> Texture<'a> {
 			Kind::D3(_, _, _) => ViewKind::D3,
 		};
 
-		let view = ImageView::create(data, &image, info.format, kind, aspects, mip_levels);
+		let (view, stencil_view) = if aspects == Aspects::DEPTH {
+			(
+				ImageView::create_depth_only(data, &image, info.format, kind, mip_levels),
+				Self::stencil_view_for(data, &image, info.format, kind, mip_levels),
+			)
+		} else {
+			let view = ImageView::create(data, &image, info.format, kind, aspects, mip_levels);
+			(view, None)
+		};
 
-		fence.wait();
-		Texture {
+		Ok(Texture {
 			data,
 			kind,
 			format: info.format,
+			extent,
+			mip_levels,
+			mipmaps: info.mipmaps,
+			layers,
+			samples: info.samples,
 			image: MaybeUninit::new(image),
 			block: MaybeUninit::new(block),
 			view,
+			stencil_view,
+			mutable_format: info.mutable_format,
+			extra_views: Cell::new(0),
 			sampler,
+		})
+	}
+
+	fn anisotropic(data: &HALData, level: AnisotropyLevel) -> Anisotropic {
+		let supported = data.features().contains(Features::SAMPLER_ANISOTROPY);
+		match level {
+			AnisotropyLevel::On(level) if supported => {
+				let max = data.limits().max_sampler_anisotropy as u8;
+				Anisotropic::On(level.min(max))
+			},
+			_ => Anisotropic::Off,
+		}
+	}
+
+	fn convert_pixels(info: &TextureInfo, pixels: &[u8]) -> Option<Vec<u8>> {
+		info.convert_on_upload.map(|conversion| {
+			assert_eq!(
+				conversion.to, info.format,
+				"Pixels must be converted to the texture's format"
+			);
+			conversion.convert(pixels)
+		})
+	}
+
+	/// Records the initial layout transitions of a freshly allocated texture
+	/// and, if `info` has pixels, the copy of them from `buffer` at
+	/// `buffer_offset`.
+	fn record_upload(
+		&self,
+		cmd_buf: &mut gfx_hal::command::CommandBuffer<Backend, Graphics>,
+		info: &TextureInfo,
+		buffer: &<Backend as gfx_hal::Backend>::Buffer,
+		buffer_offset: u64,
+	) {
+		let image = self.image();
+		let layers = self.layers;
+		if info.pixels.is_none() {
+			Self::transition_image_layout(
+				cmd_buf,
+				image,
+				0,
+				0..layers,
+				Layout::Undefined..Layout::DepthStencilAttachmentOptimal,
+			);
+			return;
+		}
+		let range = match info.mipmaps {
+			MipMaps::PreExisting(i) => 0..i,
+			_ => 0..1,
+		};
+		let texel_size = (self.format.surface_desc().bits / 8) as u64;
+		let mut buffer_offset = buffer_offset;
+		for level in range {
+			// Pre-existing levels are packed back to back in `pixels`.
+			let extent = self.extent.at_level(level);
+			let copy = BufferImageCopy {
+				buffer_offset,
+				buffer_width: 0,
+				buffer_height: 0,
+				image_layers: SubresourceLayers {
+					aspects: Aspects::COLOR,
+					level,
+					layers: 0..layers,
+				},
+				image_offset: Offset::ZERO,
+				image_extent: extent,
+			};
+			buffer_offset += extent.width as u64 *
+				extent.height as u64 *
+				extent.depth as u64 *
+				layers as u64 *
+				texel_size;
+			Self::transition_image_layout(
+				cmd_buf,
+				image,
+				level,
+				0..layers,
+				Layout::Undefined..Layout::TransferDstOptimal,
+			);
+			unsafe {
+				cmd_buf.copy_buffer_to_image(buffer, image, Layout::TransferDstOptimal, once(copy));
+			}
+			Self::transition_image_layout(
+				cmd_buf,
+				image,
+				level,
+				0..layers,
+				Layout::TransferDstOptimal..Layout::ShaderReadOnlyOptimal,
+			);
+		}
+	}
+
+	pub fn create_render_target(
+		data: &'a HALData,
+		extent: Extent,
+		format: Format,
+		usage: Usage,
+		samples: u8,
+		command_pool: &CommandPool,
+		fence: &Fence,
+	) -> Texture<'a> {
+		println!("Creating Texture");
+		let kind = Kind::D2(extent.width, extent.height, 1, samples);
+		let (aspects, layout) = if usage.contains(Usage::DEPTH_STENCIL_ATTACHMENT) {
+			(Aspects::DEPTH, Layout::DepthStencilAttachmentOptimal)
+		} else {
+			(Aspects::COLOR, Layout::ColorAttachmentOptimal)
+		};
+		let (image, block) =
+			Texture::image_block(data, kind, 1, format, usage, ViewCapabilities::empty());
+		fence.wait_n_reset();
+		command_pool.single_submit(&[], &[], fence, |cmd_buf| {
+			Self::transition_image_layout(cmd_buf, &image, 0, 0..1, Layout::Undefined..layout);
+		});
+		let view = ImageView::create(data, &image, format, ViewKind::D2, aspects, 1);
+		let stencil_view = if aspects == Aspects::DEPTH {
+			Self::stencil_view_for(data, &image, format, ViewKind::D2, 1)
+		} else {
+			None
+		};
+		fence.wait();
+		Texture {
+			data,
+			kind: ViewKind::D2,
+			format,
+			extent,
+			mip_levels: 1,
+			mipmaps: MipMaps::None,
+			layers: 1,
+			samples,
+			image: MaybeUninit::new(image),
+			block: MaybeUninit::new(block),
+			view,
+			stencil_view,
+			mutable_format: false,
+			extra_views: Cell::new(0),
+			sampler: None,
+		}
+	}
+
+	fn stencil_view_for(
+		data: &'a HALData,
+		image: &<Backend as gfx_hal::Backend>::Image,
+		format: Format,
+		kind: ViewKind,
+		mip_levels: u8,
+	) -> Option<ImageView<'a>> {
+		if format.surface_desc().aspects.contains(Aspects::STENCIL) {
+			Some(ImageView::create_stencil_only(
+				data, image, format, kind, mip_levels,
+			))
+		} else {
+			None
 		}
 	}
 
-	fn image_block<'b>(
+	fn image_block(
 		data: &'a HALData,
-		info: &'b TextureInfo<'b>,
+		kind: Kind,
+		mips: u8,
+		format: Format,
 		usage: Usage,
+		capabilities: ViewCapabilities,
 	) -> (
 		<Backend as gfx_hal::Backend>::Image,
 		<SmartAllocator<Backend> as MemoryAllocator<Backend>>::Block,
 	) {
 		let device = data.device();
-		let mips = info.mipmaps.levels(*info);
 		unsafe {
 			let mut image = device
-				.create_image(
-					info.kind,
-					mips,
-					info.format,
-					Tiling::Optimal,
-					usage,
-					ViewCapabilities::empty(),
-				)
+				.create_image(kind, mips, format, Tiling::Optimal, usage, capabilities)
 				.unwrap();
 			let reqs = device.get_image_requirements(&image);
 			let block = data
@@ -261,116 +656,265 @@ impl<'a> Texture<'a> {
 		}
 	}
 
+	/// Uploads `pixels` into mip 0 in tiles of `tile_size` texels, so images
+	/// larger than the staging buffer can be streamed through it. Each tile
+	/// must fit into `staging_buf`. The texture must be sampled, e.g. created
+	/// with `HALData::create_empty_texture`.
+	pub fn upload_tiled(&self, pixels: &[u8], tile_size: (u32, u32), staging_buf: &StagingBuffer) {
+		let texel_size = (self.format.surface_desc().bits / 8) as usize;
+		let (width, height) = (self.extent.width, self.extent.height);
+		assert_eq!(
+			pixels.len(),
+			width as usize * height as usize * texel_size,
+			"Pixel data does not match the texture size"
+		);
+		assert!(
+			tile_size.0 as u64 * tile_size.1 as u64 * texel_size as u64 <= staging_buf.size(),
+			"A {}x{} tile does not fit into the staging buffer",
+			tile_size.0,
+			tile_size.1
+		);
+		let command_pool = &staging_buf.command_pool;
+		let fence = &staging_buf.fence;
+
+		let tiles = (0..height)
+			.step_by(tile_size.1 as usize)
+			.flat_map(|y| {
+				(0..width)
+					.step_by(tile_size.0 as usize)
+					.map(move |x| (x, y))
+			})
+			.collect::<Vec<_>>();
+		let mut tile =
+			Vec::with_capacity((tile_size.0 as u64 * tile_size.1 as u64) as usize * texel_size);
+		for (idx, &(x, y)) in tiles.iter().enumerate() {
+			let tile_width = u32::min(tile_size.0, width - x);
+			let tile_height = u32::min(tile_size.1, height - y);
+			tile.clear();
+			for row in y..y + tile_height {
+				let start = (row as usize * width as usize + x as usize) * texel_size;
+				tile.extend_from_slice(&pixels[start..start + tile_width as usize * texel_size]);
+			}
+			staging_buf.upload(&tile);
+			command_pool.single_submit(&[], &[], fence, |cmd_buf| {
+				if idx == 0 {
+					Self::transition_image_layout(
+						cmd_buf,
+						self.image(),
+						0,
+						0..1,
+						Layout::ShaderReadOnlyOptimal..Layout::TransferDstOptimal,
+					);
+				}
+				let copy = BufferImageCopy {
+					buffer_offset: 0,
+					buffer_width: 0,
+					buffer_height: 0,
+					image_layers: SubresourceLayers {
+						aspects: Aspects::COLOR,
+						level: 0,
+						layers: 0..1,
+					},
+					image_offset: Offset {
+						x: x as i32,
+						y: y as i32,
+						z: 0,
+					},
+					image_extent: Extent {
+						width: tile_width,
+						height: tile_height,
+						depth: 1,
+					},
+				};
+				unsafe {
+					cmd_buf.copy_buffer_to_image(
+						&staging_buf.hal_buffer(),
+						self.image(),
+						Layout::TransferDstOptimal,
+						once(copy),
+					);
+				}
+				if idx == tiles.len() - 1 {
+					Self::transition_image_layout(
+						cmd_buf,
+						self.image(),
+						0,
+						0..1,
+						Layout::TransferDstOptimal..Layout::ShaderReadOnlyOptimal,
+					);
+				}
+			});
+		}
+		fence.wait();
+	}
+
+	/// Records and submits the mip chain generation without waiting on it.
+	/// The texture must have been created with `MipMaps::Deferred`.
+	pub fn generate_mipmaps_async<'f>(
+		&self,
+		command_pool: &CommandPool,
+		fence: &'f Fence<'f>,
+	) -> MipmapFuture<'f> {
+		assert!(
+			match self.mipmaps {
+				MipMaps::Deferred => true,
+				_ => false,
+			},
+			"Only textures created with MipMaps::Deferred can generate their mipmaps later"
+		);
+		Self::gen_mipmaps(
+			self.image(),
+			command_pool,
+			self.extent,
+			self.mip_levels,
+			self.layers,
+			fence,
+		);
+		MipmapFuture { fence }
+	}
+
 	fn gen_mipmaps(
 		image: &<Backend as gfx_hal::Backend>::Image,
 		command_pool: &CommandPool,
-		info: TextureInfo,
+		extent: Extent,
+		levels: u8,
+		layer_count: u16,
 		fence: &Fence,
 	) {
 		fence.wait_n_reset();
 		command_pool.single_submit(&[], &[], fence, |buffer| {
-			let (mut width, mut height) = {
-				let extent = info.kind.extent();
-				(extent.width, extent.height)
+			Self::record_mipmaps(buffer, image, extent, levels, layer_count)
+		});
+	}
+
+	fn record_mipmaps(
+		buffer: &mut gfx_hal::command::CommandBuffer<Backend, Graphics>,
+		image: &<Backend as gfx_hal::Backend>::Image,
+		extent: Extent,
+		levels: u8,
+		layer_count: u16,
+	) {
+		// Level 0 was uploaded and is read by shaders, the rest are untouched.
+		Self::transition_image_layout(
+			buffer,
+			image,
+			0,
+			0..layer_count,
+			Layout::ShaderReadOnlyOptimal..Layout::TransferDstOptimal,
+		);
+		for level in 1..levels {
+			Self::transition_image_layout(
+				buffer,
+				image,
+				level,
+				0..layer_count,
+				Layout::Undefined..Layout::TransferDstOptimal,
+			);
+		}
+		let (mut width, mut height) = (extent.width, extent.height);
+		for i in 1..levels {
+			let level = i - 1;
+			let range = SubresourceRange {
+				aspects: Aspects::COLOR,
+				levels: level..(level + 1),
+				layers: 0..layer_count,
 			};
-			let levels = info.mipmaps.levels(info);
-			for i in 1..levels {
-				let level = i - 1;
-				let range = SubresourceRange {
-					aspects: Aspects::COLOR,
-					levels: level..(level + 1),
-					layers: 0..1,
-				};
-				let init_barrier = Barrier::Image {
-					states: (Access::TRANSFER_WRITE, Layout::TransferDstOptimal)..
-						(Access::TRANSFER_READ, Layout::TransferSrcOptimal),
+			let init_barrier = Barrier::Image {
+				states: (Access::TRANSFER_WRITE, Layout::TransferDstOptimal)..
+					(Access::TRANSFER_READ, Layout::TransferSrcOptimal),
+				target: image,
+				families: None,
+				range: range.clone(),
+			};
+
+			unsafe {
+				buffer.pipeline_barrier(
+					PipelineStage::TRANSFER..PipelineStage::TRANSFER,
+					Dependencies::empty(),
+					once(init_barrier),
+				);
+
+				let blits = (0..layer_count).map(|layer| ImageBlit {
+					src_subresource: SubresourceLayers {
+						aspects: Aspects::COLOR,
+						level: i - 1,
+						layers: layer..layer + 1,
+					},
+					src_bounds: Offset { x: 0, y: 0, z: 0 }..Offset {
+						x: width as i32,
+						y: height as i32,
+						z: 1,
+					},
+					dst_subresource: SubresourceLayers {
+						aspects: Aspects::COLOR,
+						level: i,
+						layers: layer..layer + 1,
+					},
+					dst_bounds: Offset { x: 0, y: 0, z: 0 }..Offset {
+						x: if width > 1 { width / 2 } else { 1 } as i32,
+						y: if height > 1 { height / 2 } else { 1 } as i32,
+						z: 1,
+					},
+				});
+				buffer.blit_image(
+					image,
+					Layout::TransferSrcOptimal,
+					image,
+					Layout::TransferDstOptimal,
+					Filter::Linear,
+					blits,
+				);
+				let fin_barrier = Barrier::Image {
+					states: (Access::TRANSFER_READ, Layout::TransferSrcOptimal)..
+						(Access::SHADER_READ, Layout::ShaderReadOnlyOptimal),
 					target: image,
 					families: None,
 					range: range.clone(),
 				};
+				buffer.pipeline_barrier(
+					PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
+					Dependencies::empty(),
+					once(fin_barrier),
+				);
 
-				unsafe {
-					buffer.pipeline_barrier(
-						PipelineStage::TRANSFER..PipelineStage::TRANSFER,
-						Dependencies::empty(),
-						once(init_barrier),
-					);
-
-					let blit = ImageBlit {
-						src_subresource: SubresourceLayers {
-							aspects: Aspects::COLOR,
-							level: i - 1,
-							layers: 0..1,
-						},
-						src_bounds: Offset { x: 0, y: 0, z: 0 }..Offset {
-							x: width as i32,
-							y: height as i32,
-							z: 1,
-						},
-						dst_subresource: SubresourceLayers {
-							aspects: Aspects::COLOR,
-							level: i,
-							layers: 0..1,
-						},
-						dst_bounds: Offset { x: 0, y: 0, z: 0 }..Offset {
-							x: if width > 1 { width / 2 } else { 1 } as i32,
-							y: if height > 1 { height / 2 } else { 1 } as i32,
-							z: 1,
-						},
-					};
-					buffer.blit_image(
-						image,
-						Layout::TransferSrcOptimal,
-						image,
-						Layout::TransferDstOptimal,
-						Filter::Linear,
-						once(blit),
-					);
-					let fin_barrier = Barrier::Image {
-						states: (Access::TRANSFER_READ, Layout::TransferSrcOptimal)..
-							(Access::SHADER_READ, Layout::ShaderReadOnlyOptimal),
-						target: image,
-						families: None,
-						range: range.clone(),
-					};
-					buffer.pipeline_barrier(
-						PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
-						Dependencies::empty(),
-						once(fin_barrier),
-					);
-
-					if width > 1 {
-						width /= 2;
-					}
-					if height > 1 {
-						height /= 2;
-					}
-
-					let fin_barrier = Barrier::Image {
-						states: (Access::TRANSFER_READ, Layout::TransferSrcOptimal)..
-							(Access::SHADER_READ, Layout::ShaderReadOnlyOptimal),
-						target: image,
-						families: None,
-						range: SubresourceRange {
-							aspects: Aspects::COLOR,
-							levels: levels - 1..levels,
-							layers: 0..1,
-						},
-					};
-					buffer.pipeline_barrier(
-						PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
-						Dependencies::empty(),
-						once(fin_barrier),
-					);
+				if width > 1 {
+					width /= 2;
+				}
+				if height > 1 {
+					height /= 2;
 				}
 			}
-		});
+		}
+
+		if levels > 1 {
+			// The last level is only ever a blit destination, so it is still in
+			// TransferDstOptimal once the loop is done.
+			let last_barrier = Barrier::Image {
+				states: (Access::TRANSFER_WRITE, Layout::TransferDstOptimal)..
+					(Access::SHADER_READ, Layout::ShaderReadOnlyOptimal),
+				target: image,
+				families: None,
+				range: SubresourceRange {
+					aspects: Aspects::COLOR,
+					levels: levels - 1..levels,
+					layers: 0..layer_count,
+				},
+			};
+			unsafe {
+				buffer.pipeline_barrier(
+					PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
+					Dependencies::empty(),
+					once(last_barrier),
+				);
+			}
+		}
 	}
 
-	fn transition_image_layout(
+	pub(crate) fn transition_image_layout(
 		cmd_buf: &mut gfx_hal::command::CommandBuffer<Backend, Graphics>,
 		image: &<Backend as gfx_hal::Backend>::Image,
 		levels: u8,
+		layers: Range<u16>,
 		layout: Range<Layout>,
 	) {
 		let (aspects, access, stage) =
@@ -388,6 +932,23 @@ impl<'a> Texture<'a> {
 					Access::TRANSFER_WRITE..Access::SHADER_READ,
 					PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
 				)
+			} else if layout.start == Layout::ShaderReadOnlyOptimal &&
+				layout.end == Layout::TransferDstOptimal
+			{
+				(
+					Aspects::COLOR,
+					Access::SHADER_READ..Access::TRANSFER_WRITE,
+					PipelineStage::FRAGMENT_SHADER..PipelineStage::TRANSFER,
+				)
+			} else if layout.start == Layout::Undefined &&
+				layout.end == Layout::ColorAttachmentOptimal
+			{
+				(
+					Aspects::COLOR,
+					Access::empty()..
+						(Access::COLOR_ATTACHMENT_READ | Access::COLOR_ATTACHMENT_WRITE),
+					PipelineStage::TOP_OF_PIPE..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+				)
 			} else if layout.start == Layout::Undefined &&
 				layout.end == Layout::DepthStencilAttachmentOptimal
 			{
@@ -398,6 +959,23 @@ impl<'a> Texture<'a> {
 							Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
 					PipelineStage::TOP_OF_PIPE..PipelineStage::EARLY_FRAGMENT_TESTS,
 				)
+			} else if layout.start == Layout::ColorAttachmentOptimal &&
+				layout.end == Layout::TransferSrcOptimal
+			{
+				(
+					Aspects::COLOR,
+					Access::COLOR_ATTACHMENT_WRITE..Access::TRANSFER_READ,
+					PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::TRANSFER,
+				)
+			} else if layout.start == Layout::TransferSrcOptimal &&
+				layout.end == Layout::ColorAttachmentOptimal
+			{
+				(
+					Aspects::COLOR,
+					Access::TRANSFER_READ..
+						(Access::COLOR_ATTACHMENT_READ | Access::COLOR_ATTACHMENT_WRITE),
+					PipelineStage::TRANSFER..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+				)
 			} else {
 				panic!("Unsupported layout change");
 			};
@@ -408,7 +986,7 @@ impl<'a> Texture<'a> {
 			range: SubresourceRange {
 				aspects,
 				levels: levels..levels + 1,
-				layers: 0..1,
+				layers,
 			},
 		};
 
@@ -423,6 +1001,113 @@ impl<'a> Texture<'a> {
 
 	pub fn view(&self) -> &ImageView { &self.view }
 
+	/// Creates another view of the texture's image, e.g. to render into mip 0
+	/// while sampling the whole chain through `view()`.
+	pub fn create_view(
+		&self,
+		kind: ViewKind,
+		aspects: Aspects,
+		mip_range: Range<u8>,
+		layer_range: Range<u16>,
+	) -> ImageView {
+		assert!(
+			mip_range.start < mip_range.end && mip_range.end <= self.mip_levels,
+			"Mip range {:?} is outside of the texture's {} levels",
+			mip_range,
+			self.mip_levels
+		);
+		assert!(
+			layer_range.start < layer_range.end,
+			"Layer range {:?} is empty",
+			layer_range
+		);
+		assert!(
+			self.format.surface_desc().aspects.contains(aspects),
+			"{:?} does not have the aspects {:?}",
+			self.format,
+			aspects
+		);
+		ImageView::create_range(
+			self.data,
+			self.image(),
+			self.format,
+			kind,
+			SubresourceRange {
+				aspects,
+				levels: mip_range,
+				layers: layer_range,
+			},
+		)
+		.counted_in(&self.extra_views)
+	}
+
+	/// Views the whole texture as `view_format`, e.g. an `Rgba8Unorm` texture
+	/// as `Rgba8Uint` for integer reads in a compute shader. The texture must
+	/// have been created with `TextureInfo::mutable_format`.
+	pub fn create_reinterpreted_view(&self, view_format: Format) -> ImageView {
+		assert!(
+			self.mutable_format,
+			"Reinterpreted views need a texture created with mutable_format"
+		);
+		ImageView::create_reinterpreted(
+			self.data,
+			self.image(),
+			self.format,
+			view_format,
+			self.kind,
+			Aspects::COLOR,
+			self.mip_levels,
+		)
+		.counted_in(&self.extra_views)
+	}
+
+	pub fn sample_count(&self) -> u8 { self.samples }
+
+	/// A framebuffer rendering into mip 0 of one face of a cube map, e.g. to
+	/// capture an environment map or a point light's shadows. `pass` must have
+	/// this texture's format as its only attachment.
+	pub fn face_framebuffer<'p>(
+		&'p self,
+		face: CubeFace,
+		pass: &'p RenderPass<'p>,
+	) -> FrameBuffer<'p> {
+		assert_eq!(self.layers, 6, "Face framebuffers need a cube map texture");
+		let aspects = if self.format.surface_desc().aspects.contains(Aspects::DEPTH) {
+			Aspects::DEPTH
+		} else {
+			Aspects::COLOR
+		};
+		let layer = face as u16;
+		let view = ImageView::create_range(
+			self.data,
+			self.image(),
+			self.format,
+			ViewKind::D2,
+			SubresourceRange {
+				aspects,
+				levels: 0..1,
+				layers: layer..layer + 1,
+			},
+		)
+		.counted_in(&self.extra_views);
+		let size = Extent {
+			depth: 1,
+			..self.extent
+		};
+		FrameBuffer::from_owned_view(pass, view, size)
+	}
+
+	/// The number of live views of this texture: the primary view, the
+	/// stencil view of depth textures and every view from `create_view`,
+	/// `create_reinterpreted_view` or `face_framebuffer` that was not dropped
+	/// yet.
+	/// A count growing each frame points at a view leaked per frame.
+	pub fn view_count(&self) -> usize {
+		1 + self.stencil_view.is_some() as usize + self.extra_views.get()
+	}
+
+	pub fn stencil_view(&self) -> Option<&ImageView> { self.stencil_view.as_ref() }
+
 	pub fn sampler(&self) -> &Option<Sampler> { &self.sampler }
 
 	pub fn descriptor(&self) -> Descriptor<Backend> {
@@ -437,6 +1122,104 @@ impl<'a> Texture<'a> {
 	}
 }
 
+impl<'a, 'b> StagingBatch<'a, 'b> {
+	// Keeps every copy offset a multiple of the texel size.
+	const ALIGNMENT: usize = 16;
+
+	pub fn create(data: &'a HALData) -> StagingBatch<'a, 'b> {
+		StagingBatch {
+			data,
+			infos: Vec::new(),
+		}
+	}
+
+	/// Queues a texture and returns its index in the result of `flush`.
+	pub fn add(&mut self, info: TextureInfo<'b>) -> usize {
+		self.infos.push(info);
+		self.infos.len() - 1
+	}
+
+	pub fn len(&self) -> usize { self.infos.len() }
+
+	pub fn is_empty(&self) -> bool { self.infos.is_empty() }
+
+	/// Creates every queued texture, uploading all of their pixels through
+	/// `staging_buf` in a single command buffer. The pixels of all textures
+	/// together must fit into the staging buffer.
+	pub fn flush(self, staging_buf: &StagingBuffer) -> Vec<Texture<'a>> {
+		let textures = self
+			.infos
+			.iter()
+			.map(|info| {
+				Texture::allocate(self.data, *info, info.pixels.is_some())
+					.expect("Unsupported texture sample count")
+			})
+			.collect::<Vec<_>>();
+
+		let mut packed = Vec::new();
+		let offsets = self
+			.infos
+			.iter()
+			.map(|info| {
+				let offset = packed.len() as u64;
+				if let Some(pixels) = info.pixels {
+					match Texture::convert_pixels(info, pixels) {
+						Some(converted) => packed.extend_from_slice(&converted),
+						None => packed.extend_from_slice(pixels),
+					}
+					let padding =
+						(Self::ALIGNMENT - packed.len() % Self::ALIGNMENT) % Self::ALIGNMENT;
+					packed.resize(packed.len() + padding, 0);
+				}
+				offset
+			})
+			.collect::<Vec<_>>();
+		assert!(
+			packed.len() as u64 <= staging_buf.size(),
+			"Batched pixels take {} bytes but the staging buffer only holds {}",
+			packed.len(),
+			staging_buf.size()
+		);
+
+		let fence = &staging_buf.fence;
+		if !packed.is_empty() {
+			staging_buf.upload(&packed);
+		} else {
+			fence.wait_n_reset();
+		}
+		staging_buf
+			.command_pool
+			.single_submit(&[], &[], fence, |cmd_buf| {
+				for ((texture, info), offset) in textures.iter().zip(&self.infos).zip(offsets) {
+					texture.record_upload(cmd_buf, info, staging_buf.hal_buffer(), offset);
+				}
+				for (texture, info) in textures.iter().zip(&self.infos) {
+					if let MipMaps::Generate = info.mipmaps {
+						Texture::record_mipmaps(
+							cmd_buf,
+							texture.image(),
+							texture.extent,
+							texture.mip_levels,
+							texture.layers,
+						);
+					}
+				}
+			});
+		fence.wait();
+		textures
+	}
+}
+
+pub struct MipmapFuture<'a> {
+	fence: &'a Fence<'a>,
+}
+
+impl<'a> MipmapFuture<'a> {
+	pub fn is_ready(&self) -> bool { self.fence.status() == FenceStatus::Signaled }
+
+	pub fn wait(self) { self.fence.wait() }
+}
+
 impl<'a> Drop for Texture<'a> {
 	fn drop(&mut self) {
 		let img = MaybeUninit::take(&mut self.image);
@@ -452,3 +1235,39 @@ impl<'a> Drop for Texture<'a> {
 		println!("Dropped Texture");
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn lod_range_ends_at_the_last_level() {
+		// A 128x128 chain down to 2x2 has 7 levels.
+		assert_eq!(lod_range(7), 0.0..6.0);
+	}
+
+	#[test]
+	fn single_level_has_no_lod_range() {
+		assert_eq!(lod_range(1), 0.0..0.0);
+	}
+
+	#[test]
+	fn rgba8_length_does_not_overflow_u32() {
+		let err = TextureInfo::from_rgba8(&[], 1 << 16, 1 << 16).err();
+		assert_eq!(
+			err,
+			Some(DimensionMismatch {
+				expected: (1 << 16) * (1 << 16) * 4,
+				actual: 0,
+			})
+		);
+	}
+
+	#[test]
+	fn rgba8_mip_chain_length() {
+		// 4x4, 2x2 and 1x1 levels.
+		let chain = [0; (16 + 4 + 1) * 4];
+		assert!(TextureInfo::from_rgba8_with_mips(&chain, 4, 4, 3).is_ok());
+		assert!(TextureInfo::from_rgba8_with_mips(&chain[4..], 4, 4, 3).is_err());
+	}
+}
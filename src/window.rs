@@ -17,8 +17,12 @@ use crate::gfx_back::{
 	Surface,
 };
 
+#[derive(Debug)]
+pub struct CursorLockError(pub String);
+
 pub struct Window {
 	events_loop: EventsLoop,
+	/// Logical size of the window. Use `physical_size` for the swapchain.
 	dims: (u32, u32),
 	#[cfg(not(feature = "gl"))]
 	window: BackWindow,
@@ -69,12 +73,51 @@ impl Window {
 	#[cfg(not(feature = "gl"))]
 	pub fn window(&self) -> &BackWindow { &self.window }
 
+	/// Resizes the window to the logical size `dims`. The physical size of
+	/// the window is `dims` multiplied by the scale factor.
 	#[cfg(not(feature = "gl"))]
 	pub fn resize(&mut self, dims: (u32, u32)) {
 		self.dims = dims;
 		self.window.set_inner_size(dims.into());
 	}
 
+	/// Limits how small the user can resize the window, in logical pixels.
+	#[cfg(not(feature = "gl"))]
+	pub fn set_min_size(&self, min: (u32, u32)) { self.window.set_min_dimensions(Some(min.into())) }
+
+	/// Limits how large the user can resize the window, in logical pixels.
+	#[cfg(not(feature = "gl"))]
+	pub fn set_max_size(&self, max: (u32, u32)) { self.window.set_max_dimensions(Some(max.into())) }
+
+	#[cfg(not(feature = "gl"))]
+	pub fn scale_factor(&self) -> f64 { self.window.get_hidpi_factor() }
+
+	/// The size of the window in pixels, which is what the swapchain should be
+	/// created with on HiDPI monitors. Queried from the window, so it is exact
+	/// even when the logical size doesn't scale to whole pixels.
+	#[cfg(not(feature = "gl"))]
+	pub fn physical_size(&self) -> (u32, u32) {
+		let size = self
+			.window
+			.get_inner_size()
+			.expect("The window has been closed")
+			.to_physical(self.scale_factor());
+		(size.width.round() as u32, size.height.round() as u32)
+	}
+
+	#[cfg(not(feature = "gl"))]
+	pub fn set_cursor_visible(&self, visible: bool) { self.window.hide_cursor(!visible) }
+
+	#[cfg(not(feature = "gl"))]
+	pub fn lock_cursor(&self) -> Result<(), CursorLockError> {
+		self.window.grab_cursor(true).map_err(CursorLockError)
+	}
+
+	#[cfg(not(feature = "gl"))]
+	pub fn unlock_cursor(&self) -> Result<(), CursorLockError> {
+		self.window.grab_cursor(false).map_err(CursorLockError)
+	}
+
 	pub fn poll_events(&mut self, mut f: impl FnMut(Event)) {
 		let events_loop = &mut self.events_loop;
 		let mut new_dims = None;
@@ -0,0 +1,75 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{
+	bracketed,
+	parenthesized,
+	parse::{
+		Parse,
+		ParseStream,
+	},
+	parse_macro_input,
+	punctuated::Punctuated,
+	DeriveInput,
+	Ident,
+	Token,
+};
+
+struct StagesAttr {
+	stages: Vec<Ident>,
+}
+
+impl Parse for StagesAttr {
+	fn parse(input: ParseStream) -> syn::Result<Self> {
+		let content;
+		parenthesized!(content in input);
+		let key: Ident = content.parse()?;
+		if key != "stages" {
+			return Err(syn::Error::new(key.span(), "Expected `stages = [...]`"));
+		}
+		content.parse::<Token![=]>()?;
+		let list;
+		bracketed!(list in content);
+		let stages = Punctuated::<Ident, Token![,]>::parse_terminated(&list)?;
+		Ok(StagesAttr {
+			stages: stages.into_iter().collect(),
+		})
+	}
+}
+
+#[proc_macro_derive(PushConstantInfo, attributes(push_constant))]
+pub fn derive_push_constant_info(input: TokenStream) -> TokenStream {
+	let input = parse_macro_input!(input as DeriveInput);
+	let name = &input.ident;
+	let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+	let attr = input
+		.attrs
+		.iter()
+		.find(|attr| attr.path.is_ident("push_constant"));
+	let stages = match attr {
+		Some(attr) => match syn::parse2::<StagesAttr>(attr.tts.clone()) {
+			Ok(parsed) => parsed.stages,
+			Err(err) => return err.to_compile_error().into(),
+		},
+		None =>
+			return syn::Error::new(
+				Span::call_site(),
+				"PushConstantInfo requires a #[push_constant(stages = [...])] attribute",
+			)
+			.to_compile_error()
+			.into(),
+	};
+
+	let expanded = quote! {
+		impl #impl_generics ::villkiss::shader::PushConstantInfo for #name #ty_generics #where_clause {
+			const STAGES: &'static [::villkiss::gfx_hal::pso::ShaderStageFlags] = &[
+				#(::villkiss::gfx_hal::pso::ShaderStageFlags::#stages,)*
+			];
+			const SIZE: u32 = ::std::mem::size_of::<#name #ty_generics>() as u32;
+		}
+	};
+	expanded.into()
+}